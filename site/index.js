@@ -4,7 +4,7 @@
 //   position: 'start'
 // }
 // var board = Chessboard('myBoard', config)
-import initSync, {set_state, get_state, get_state_fen, make_move, get_valid_moves, in_check, change_promotion, _eval, engine_move, get_zob_hash} from './node_modules/chess/chess.js';
+import initSync, {set_state, get_state, get_state_fen, make_move, get_valid_moves, in_check, _eval, engine_move, get_zob_hash, get_game_status, undo, redo, get_moves_san, get_ply, get_pv} from './node_modules/chess/chess.js';
 
 async function setBoard(fen) {
     await initSync();
@@ -39,17 +39,14 @@ async function displayBoard() {
         }
     }
     if (!game_over) {
-        if (checkMate()) {
+        let status = get_game_status();
+        if (status !== 'Ongoing') {
             game_over = true;
-            // clearInterval(engine);
-            alert(`${state.turn} loses by checkmate!`);
-        } else if (stalemate()) {
-            game_over = true;
-            // clearInterval(engine);
-            alert(`Stalemate! The game is a draw.`);
+            alert(gameOverMessage(status));
         }
     }
     document.getElementById('zob-hash').innerText = `Zobrist Hash: ${get_zob_hash()}`;
+    document.getElementById('move-list').innerText = get_moves_san().slice(0, get_ply()).join(' ');
 }
 
 function pieceClicked() {
@@ -72,7 +69,7 @@ function pieceClicked() {
 
     valid_moves = get_valid_moves(activePiece);
     if (valid_moves.includes(squareNum)) {
-        make_move(activePiece, squareNum);
+        make_move(activePiece, squareNum, promotion);
         activePiece = null; 
     }
     else {
@@ -92,41 +89,21 @@ function emptyClicked() {
     let squareNum = (7 - 'h'.charCodeAt(0) + this.id.charCodeAt(0)) + (parseInt(8-this.id[1]))*8;
     valid_moves = get_valid_moves(activePiece);
     if (valid_moves.includes(squareNum)) {
-        make_move(activePiece, squareNum);
+        make_move(activePiece, squareNum, promotion);
     }
     activePiece = null; 
     displayBoard();
 }
 
-function checkMate() {
-    if (in_check()) {
-        for (let i = 0; i < 64; i++) {
-            let piece = state.board[i];
-            if (piece && piece.color === state.turn) {
-                let moves = get_valid_moves(i);
-                if (moves.length > 0) {
-                    return false; // There are still valid moves
-                }
-            }
-        }
-        return true;
-    }
-    return false;
-}
-function stalemate() {
-    if (!in_check()) {
-        for (let i = 0; i < 64; i++) {
-            let piece = state.board[i];
-            if (piece && piece.color === state.turn) {
-                let moves = get_valid_moves(i);
-                if (moves.length > 0) {
-                    return false; // There are still valid moves
-                }
-            }
-        }
-        return true;
+function gameOverMessage(status) {
+    switch (status) {
+        case 'Checkmate': return `${state.turn} loses by checkmate!`;
+        case 'Stalemate': return 'Stalemate! The game is a draw.';
+        case 'ThreefoldRepetition': return 'Draw by threefold repetition.';
+        case 'FiftyMoveRule': return 'Draw by the fifty-move rule.';
+        case 'SeventyFiveMoveRule': return 'Draw by the seventy-five-move rule.';
+        case 'InsufficientMaterial': return 'Draw by insufficient material.';
     }
-    return false;
 }
 
 let board = document.getElementById('board');
@@ -142,17 +119,20 @@ for (let i = 0; i < 64; i++) {
 
 let activePiece = null;
 let valid_moves = [];
+let promotion = 'Queen';
 let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 let state = null;
 let game_over = false;
+let player = "White"; // the engine plays the other side
 
 setBoard(start);
 
 // Engine vs Player
 const engine = setInterval(() => {
-    if (state.turn === "White") return; 
+    if (state.turn === player) return;
     let x = engine_move();
     console.log(`Engine move: ${x}`);
+    document.getElementById('pv').innerText = `${x}, PV: ${get_pv().join(' ')}`;
     displayBoard();
 }, 1000);
 
@@ -171,7 +151,7 @@ document.getElementById('fenSubmit').onclick = function() {
     setBoard(fen).catch(err => {
         setBoard(fallback);
         console.error("Error setting board:", err);
-        alert("Invalid FEN string. Please check the format.");
+        alert(err);
     });
     game_over = false;
 }
@@ -185,14 +165,34 @@ document.getElementById('getfen').onclick = function() {
 }
 
 document.getElementById('promPiece').onchange = function() {
-    change_promotion(this.value);
+    promotion = this.value;
 }
 
 document.getElementById('engineMove').onclick = () => {
     // await initSync();
     let x = engine_move();
     console.log(`Engine move: ${x}`);
+    document.getElementById('pv').innerText = `${x}, PV: ${get_pv().join(' ')}`;
     displayBoard();
 }
 
+// Takes back the engine's reply along with your move, so it's your turn again
+document.getElementById('undo').onclick = () => {
+    let undone = false;
+    while (undo()) {
+        undone = true;
+        if (get_state().turn === player) break;
+    }
+    if (undone) {
+        game_over = false;
+        activePiece = null;
+        displayBoard();
+    }
+}
 
+document.getElementById('redo').onclick = () => {
+    if (redo()) {
+        activePiece = null;
+        displayBoard();
+    }
+}
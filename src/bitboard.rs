@@ -0,0 +1,140 @@
+// Bitboards use the same square numbering as State::board: bit 0 is a8, bit 63 is h1.
+
+use crate::magic::SLIDER_ATTACKS;
+
+pub const fn bit(sq: usize) -> u64 {
+    1u64 << sq
+}
+
+// Iterates over the squares of the set bits, lowest first
+pub fn squares(mut bb: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bb == 0 {
+            return None;
+        }
+        let sq = bb.trailing_zeros() as usize;
+        bb &= bb - 1;
+        Some(sq)
+    })
+}
+
+const fn on_board(x: i32, y: i32) -> bool {
+    x >= 0 && x < 8 && y >= 0 && y < 8
+}
+
+// Squares reachable by a single (dx, dy) jump from every square
+const fn leaper_table(deltas: &[(i32, i32)]) -> [u64; 64] {
+    let mut table = [0u64; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (x, y) = ((sq % 8) as i32, (sq / 8) as i32);
+        let mut i = 0;
+        while i < deltas.len() {
+            let (nx, ny) = (x + deltas[i].0, y + deltas[i].1);
+            if on_board(nx, ny) {
+                table[sq] |= bit((ny * 8 + nx) as usize);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+// Every square from (not including) each square to the edge of the board in one direction
+const fn ray_table(dx: i32, dy: i32) -> [u64; 64] {
+    let mut table = [0u64; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (mut x, mut y) = ((sq % 8) as i32 + dx, (sq / 8) as i32 + dy);
+        while on_board(x, y) {
+            table[sq] |= bit((y * 8 + x) as usize);
+            x += dx;
+            y += dy;
+        }
+        sq += 1;
+    }
+    table
+}
+
+pub const KNIGHT_ATTACKS: [u64; 64] = leaper_table(&[
+    (-2, -1), (-1, -2), (1, -2), (2, -1),
+    (2, 1), (1, 2), (-1, 2), (-2, 1)
+]);
+
+pub const KING_ATTACKS: [u64; 64] = leaper_table(&[
+    (-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)
+]);
+
+// Indexed by PieceColor; white pawns move towards rank 8 (lower indices)
+pub const PAWN_ATTACKS: [[u64; 64]; 2] = [
+    leaper_table(&[(-1, -1), (1, -1)]),
+    leaper_table(&[(-1, 1), (1, 1)]),
+];
+
+// (dx, dy) and the matching rays; the first four are rook directions, the rest bishop ones
+const DIRECTIONS: [(i32, i32); 8] = [(0, -1), (0, 1), (-1, 0), (1, 0), (-1, -1), (1, -1), (-1, 1), (1, 1)];
+const RAYS: [[u64; 64]; 8] = [
+    ray_table(DIRECTIONS[0].0, DIRECTIONS[0].1),
+    ray_table(DIRECTIONS[1].0, DIRECTIONS[1].1),
+    ray_table(DIRECTIONS[2].0, DIRECTIONS[2].1),
+    ray_table(DIRECTIONS[3].0, DIRECTIONS[3].1),
+    ray_table(DIRECTIONS[4].0, DIRECTIONS[4].1),
+    ray_table(DIRECTIONS[5].0, DIRECTIONS[5].1),
+    ray_table(DIRECTIONS[6].0, DIRECTIONS[6].1),
+    ray_table(DIRECTIONS[7].0, DIRECTIONS[7].1),
+];
+
+// Ray in direction `dir` cut off after the first occupied square
+fn ray_attacks(sq: usize, occupied: u64, dir: usize) -> u64 {
+    let ray = RAYS[dir][sq];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let (dx, dy) = DIRECTIONS[dir];
+    let first = if dy * 8 + dx > 0 {
+        blockers.trailing_zeros()
+    } else {
+        63 - blockers.leading_zeros()
+    };
+    ray ^ RAYS[dir][first as usize]
+}
+
+// Ray by ray slider attacks, only used to fill the magic tables
+pub fn rook_rays(sq: usize, occupied: u64) -> u64 {
+    (0..4).fold(0, |acc, dir| acc | ray_attacks(sq, occupied, dir))
+}
+
+pub fn bishop_rays(sq: usize, occupied: u64) -> u64 {
+    (4..8).fold(0, |acc, dir| acc | ray_attacks(sq, occupied, dir))
+}
+
+// Squares whose occupancy can change a slider's attacks: its rays without the last square
+pub fn relevant_occupancy(sq: usize, rook: bool) -> u64 {
+    let dirs = if rook {0..4} else {4..8};
+    dirs.fold(0, |acc, dir| {
+        let ray = RAYS[dir][sq];
+        let (dx, dy) = DIRECTIONS[dir];
+        let last = if ray == 0 {
+            0
+        } else if dy * 8 + dx > 0 {
+            bit(63 - ray.leading_zeros() as usize)
+        } else {
+            bit(ray.trailing_zeros() as usize)
+        };
+        acc | (ray & !last)
+    })
+}
+
+pub fn rook_attacks(sq: usize, occupied: u64) -> u64 {
+    SLIDER_ATTACKS.rook(sq, occupied)
+}
+
+pub fn bishop_attacks(sq: usize, occupied: u64) -> u64 {
+    SLIDER_ATTACKS.bishop(sq, occupied)
+}
+
+pub fn queen_attacks(sq: usize, occupied: u64) -> u64 {
+    rook_attacks(sq, occupied) | bishop_attacks(sq, occupied)
+}
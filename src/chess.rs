@@ -1,8 +1,12 @@
 use serde::Serialize;
-use std::{collections::HashMap, ops::Not};
+use std::fmt;
+use std::ops::Not;
 
+use crate::bitboard::{bit, bishop_attacks, queen_attacks, rook_attacks, squares, KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
 use crate::zobrist::ZOBRIST_KEYS;
 
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 
 #[derive(Clone, Copy, PartialEq, Serialize)]
 pub enum PieceType {
@@ -14,21 +18,148 @@ pub enum PieceType {
     King
 }
 
-#[derive(Clone, Copy, PartialEq, Serialize)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
 pub enum PieceColor {
     White,
     Black,
 }
 
+impl Not for PieceColor {
+    type Output = PieceColor;
+
+    fn not(self) -> PieceColor {
+        match self {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Serialize)]
 pub struct Piece {
     pub color: PieceColor,
     pub tp: PieceType,
 }
 
+// Move flags, stored in the top 4 bits of a Move
+pub const QUIET: u16 = 0;
+pub const DOUBLE_PUSH: u16 = 1;
+pub const KING_CASTLE: u16 = 2;
+pub const QUEEN_CASTLE: u16 = 3;
+pub const CAPTURE: u16 = 4;
+pub const EN_PASSANT: u16 = 5;
+pub const PROMOTION: u16 = 8; // low two bits pick the piece: knight, bishop, rook, queen
+
+// 0000 000000 000000
+// flags  to    from
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub struct Move(pub u16);
+
+impl Move {
+    pub const NULL: Move = Move(0);
+
+    pub fn new(from: usize, to: usize, flags: u16) -> Self {
+        Move(from as u16 | (to as u16) << 6 | flags << 12)
+    }
+
+    pub fn from(self) -> usize {
+        (self.0 & 0x3f) as usize
+    }
+
+    pub fn to(self) -> usize {
+        (self.0 >> 6 & 0x3f) as usize
+    }
+
+    pub fn flags(self) -> u16 {
+        self.0 >> 12
+    }
+
+    pub fn is_capture(self) -> bool {
+        self.flags() & CAPTURE != 0
+    }
+
+    pub fn is_en_passant(self) -> bool {
+        self.flags() == EN_PASSANT
+    }
+
+    pub fn is_castle(self) -> bool {
+        self.flags() == KING_CASTLE || self.flags() == QUEEN_CASTLE
+    }
+
+    pub fn is_promotion(self) -> bool {
+        self.flags() & PROMOTION != 0
+    }
+
+    pub fn promotion(self) -> Option<PieceType> {
+        if !self.is_promotion() {
+            return None;
+        }
+        Some([PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen][(self.flags() & 3) as usize])
+    }
+
+    // Coordinate notation as used by UCI: "e2e4", "e7e8q", "e1g1" for castling
+    pub fn to_uci(self) -> String {
+        let mut s = square_name(self.from()) + &square_name(self.to());
+        match self.promotion() {
+            Some(PieceType::Knight) => s.push('n'),
+            Some(PieceType::Bishop) => s.push('b'),
+            Some(PieceType::Rook) => s.push('r'),
+            Some(_) => s.push('q'),
+            None => {}
+        }
+        s
+    }
+}
+
+pub fn promotion_flag(tp: PieceType) -> u16 {
+    PROMOTION | match tp {
+        PieceType::Knight => 0,
+        PieceType::Bishop => 1,
+        PieceType::Rook => 2,
+        _ => 3,
+    }
+}
+
+
+// "a8" for 0 up to "h1" for 63
+pub fn square_name(sq: usize) -> String {
+    let file = (b'a' + (sq % 8) as u8) as char;
+    let rank = (b'8' - (sq / 8) as u8) as char;
+    format!("{}{}", file, rank)
+}
 
+pub fn parse_square(s: &str) -> Option<usize> {
+    let mut chars = s.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some((b'8' - rank as u8) as usize * 8 + (file as u8 - b'a') as usize)
+}
 
+// The legal move written in coordinate notation, see Move::to_uci. Castling
+// may also be given as the king taking its own rook ("e1h1"), as Chess960 GUIs send it.
+pub fn parse_uci_move(state: &State, s: &str) -> Option<Move> {
+    if s.len() < 4 || s.len() > 5 || !s.is_ascii() {return None;}
+    let from = parse_square(&s[0..2])?;
+    let mut to = parse_square(&s[2..4])?;
+    let promotion = match s.chars().nth(4) {
+        Some('n') => Some(PieceType::Knight),
+        Some('b') => Some(PieceType::Bishop),
+        Some('r') => Some(PieceType::Rook),
+        Some('q') => Some(PieceType::Queen),
+        Some(_) => return None,
+        None => None,
+    };
 
+    let piece = state.board[from]?;
+    if piece.color != state.turn {return None;}
+    if piece.tp == PieceType::King && state.board[to] == Some(Piece {color: piece.color, tp: PieceType::Rook}) {
+        to = if to < from {from - 2} else {from + 2};
+    }
+    get_valid_moves_for(from, state).into_iter().find(|mv| mv.to() == to && mv.promotion() == promotion)
+}
 
 #[derive(Clone, Serialize)]
 pub struct State {
@@ -39,9 +170,14 @@ pub struct State {
     pub en_passant: (usize, usize), // (rank, file)
     pub halfmove_clock: u32,
     pub fullmove_number: u32,
-    pub promotion: PieceType,
     #[serde(skip)]
     pub zob_hash: u64,
+    #[serde(skip)]
+    pub pieces: [u64; 6], // bitboard per PieceType
+    #[serde(skip)]
+    pub colors: [u64; 2], // bitboard per PieceColor
+    #[serde(skip)]
+    pub occupied: u64,
 }
 
 impl State {
@@ -53,8 +189,10 @@ impl State {
             en_passant: (8, 8),
             halfmove_clock: 0,
             fullmove_number: 1,
-            promotion: PieceType::Queen, 
             zob_hash: ZOBRIST_KEYS.castling[0],
+            pieces: [0; 6],
+            colors: [0; 2],
+            occupied: 0,
         }
     }
 
@@ -65,6 +203,9 @@ impl State {
         x += piece.tp as usize;
         self.zob_hash ^= ZOBRIST_KEYS.pieces[ind][x];
         self.board[ind] = Some(piece);
+        self.pieces[piece.tp as usize] |= bit(ind);
+        self.colors[piece.color as usize] |= bit(ind);
+        self.occupied |= bit(ind);
     }
 
     pub fn remove_piece(&mut self, ind: usize) {
@@ -73,15 +214,18 @@ impl State {
             x += piece.tp as usize;
             self.zob_hash ^= ZOBRIST_KEYS.pieces[ind][x];
             self.board[ind] = None;
+            self.pieces[piece.tp as usize] &= !bit(ind);
+            self.colors[piece.color as usize] &= !bit(ind);
+            self.occupied &= !bit(ind);
         }
     }
 
+    pub fn pieces_of(&self, tp: PieceType, color: PieceColor) -> u64 {
+        self.pieces[tp as usize] & self.colors[color as usize]
+    }
+
     pub fn switch_turn(&mut self) {
-        if self.turn == PieceColor::White {
-            self.turn = PieceColor::Black;
-        } else {
-            self.turn = PieceColor::White;
-        }
+        self.turn = !self.turn;
         self.zob_hash ^= ZOBRIST_KEYS.turn;
     }
 
@@ -92,187 +236,370 @@ impl State {
         self.zob_hash ^= ZOBRIST_KEYS.castling[cs as usize];
     }
 
+    // Expects the board and side to move that the square belongs to, both when
+    // setting it and when replacing it
     pub fn set_en_passant(&mut self, rank: usize, file: usize) {
         if (rank, file) == self.en_passant {return;}
-        if self.en_passant.1 != 8 {
-            self.zob_hash ^= ZOBRIST_KEYS.enpassant[self.en_passant.1];
-        }
+        self.zob_hash ^= self.en_passant_key();
         self.en_passant = (rank, file);
-        if file != 8 {
-            self.zob_hash ^= ZOBRIST_KEYS.enpassant[file];
-        }
-    }
-}
-
-pub fn state_from_fen(fen: String, state:&mut State) -> Result<(),()> {
-
-    let piece_from_char = HashMap::from([
-        ('p', Piece {color: PieceColor::Black, tp: PieceType::Pawn}),
-        ('P', Piece {color: PieceColor::White, tp: PieceType::Pawn}),
-        ('n', Piece {color: PieceColor::Black, tp: PieceType::Knight}),
-        ('N', Piece {color: PieceColor::White, tp: PieceType::Knight}),
-        ('b', Piece {color: PieceColor::Black, tp: PieceType::Bishop}),
-        ('B', Piece {color: PieceColor::White, tp: PieceType::Bishop}),
-        ('r', Piece {color: PieceColor::Black, tp: PieceType::Rook}),
-        ('R', Piece {color: PieceColor::White, tp: PieceType::Rook}),
-        ('q', Piece {color: PieceColor::Black, tp: PieceType::Queen}),
-        ('Q', Piece {color: PieceColor::White, tp: PieceType::Queen}),
-        ('k', Piece {color: PieceColor::Black, tp: PieceType::King}),
-        ('K', Piece {color: PieceColor::White, tp: PieceType::King}),
-    ]);
-    let parts = fen.split_whitespace().collect::<Vec<&str>>();
-    if parts.len() != 6 {
-        return Err(());
-    }
-    let mut rank = 7;
-    let mut file = 0;
-    for c in parts[0].chars() {
-        match c {
-            '1'..='9' => {
-                let emp = c.to_digit(10).unwrap() as usize;
-                for i in 0..emp {
-                    state.remove_piece((7-rank)*8 + file + i);
-                }
-                file += emp;
-            }
-            '/' => {
-                if file != 8 {
-                    return Err(());
-                }
-                rank -= 1;
-                file = 0;
-            }
-            'p' | 'P' | 'n' | 'N' | 'b' | 'B' | 'r' | 'R' | 'q' | 'Q' | 'k' | 'K' => {
-                state.set_piece((7-rank)*8 + file, piece_from_char[&c]);
-                file += 1;
-            }
-            _ => {return Err(())}
+        self.zob_hash ^= self.en_passant_key();
+    }
+
+    // The en passant file is only hashed when a pawn of the side to move stands
+    // next to the pushed pawn, otherwise the position repeats one without it
+    fn en_passant_key(&self) -> u64 {
+        let (rank, file) = self.en_passant;
+        if file == 8 || PAWN_ATTACKS[(!self.turn) as usize][rank * 8 + file] & self.pieces_of(PieceType::Pawn, self.turn) == 0 {
+            return 0;
         }
+        ZOBRIST_KEYS.enpassant[file]
     }
-    if rank != 0 || file != 8 {
-        return Err(());
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::new()
     }
+}
+
+fn piece_from_char(c: char) -> Option<Piece> {
+    let tp = match c.to_ascii_lowercase() {
+        'p' => PieceType::Pawn,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'r' => PieceType::Rook,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() {PieceColor::White} else {PieceColor::Black};
+    Some(Piece {color, tp})
+}
 
-    match parts[1] {
-        "w" => if state.turn==PieceColor::Black {state.switch_turn();},
-        "b" => if state.turn==PieceColor::White {state.switch_turn();},
-        _ => return Err(()),
+pub fn piece_to_char(piece: Piece) -> char {
+    let c = match piece.tp {
+        PieceType::Pawn => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
     };
+    if piece.color == PieceColor::White {c.to_ascii_uppercase()} else {c}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FenField {
+    Placement,
+    SideToMove,
+    Castling,
+    EnPassant,
+    HalfmoveClock,
+    FullmoveNumber,
+}
+
+impl fmt::Display for FenField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FenField::Placement => "piece placement",
+            FenField::SideToMove => "side to move",
+            FenField::Castling => "castling rights",
+            FenField::EnPassant => "en passant square",
+            FenField::HalfmoveClock => "halfmove clock",
+            FenField::FullmoveNumber => "fullmove number",
+        })
+    }
+}
+
+// `index` is the byte offset into the FEN string of the offending character or field
+#[derive(Clone, PartialEq, Debug)]
+pub enum FenError {
+    FieldCount(usize),
+    InvalidChar {field: FenField, index: usize, c: char},
+    RankLength {rank: usize, index: usize}, // squares of the rank don't add up to 8
+    RankCount(usize),
+    InvalidField {field: FenField, index: usize},
+    // well formed, but not a position that can occur in a game
+    KingCount {color: PieceColor, count: u32},
+    PawnOnBackRank {square: usize},
+    CastlingWithoutRook {right: char}, // or without the king on its square
+    EnPassantImpossible {index: usize}, // no pawn can just have made a double push there
+    OpponentInCheck,
+}
 
-    state.set_castle(0);
-    if parts[2] != "-" {
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::FieldCount(n) => write!(f, "expected 6 fields, found {}", n),
+            FenError::InvalidChar {field, index, c} => write!(f, "unexpected '{}' in {} at character {}", c, field, index + 1),
+            FenError::RankLength {rank, index} => write!(f, "rank {} does not have 8 squares (character {})", rank, index + 1),
+            FenError::RankCount(n) => write!(f, "expected 8 ranks, found {}", n),
+            FenError::InvalidField {field, index} => write!(f, "invalid {} at character {}", field, index + 1),
+            FenError::KingCount {color, count} => write!(f, "{:?} has {} kings", color, count),
+            FenError::PawnOnBackRank {square} => write!(f, "pawn on the back rank at {}", square_name(*square)),
+            FenError::CastlingWithoutRook {right} => write!(f, "castling right {} without king and rook on their squares", right),
+            FenError::EnPassantImpossible {index} => write!(f, "en passant square at character {} does not follow a double pawn push", index + 1),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl State {
+    // Castling rights may be written as KQkq or with the rook files of
+    // Shredder-FEN/X-FEN (HAha); only rooks on the a and h files are supported.
+    // Only positions that can come up in a game are accepted.
+    pub fn from_fen(fen: &str) -> Result<State, FenError> {
+        let mut state = State::new();
+        let fields = fen.split_ascii_whitespace()
+            .map(|field| (field.as_ptr() as usize - fen.as_ptr() as usize, field))
+            .collect::<Vec<(usize, &str)>>();
+        if fields.len() != 6 {
+            return Err(FenError::FieldCount(fields.len()));
+        }
 
-        let mut cs = 0;
-        for c in parts[2].chars() {
+        let (start, placement) = fields[0];
+        let (mut row, mut file) = (0, 0);
+        for (i, c) in placement.char_indices() {
+            let index = start + i;
             match c {
-                'K' => cs |= 1, // White kingside
-                'Q' => cs |= 1<<1, // White queenside
-                'k' => cs |= 1<<2, // Black kingside
-                'q' => cs |= 1<<3, // Black queenside
-                _ => return Err(()),
+                '1'..='8' => file += c.to_digit(10).unwrap() as usize,
+                '/' => {
+                    if file != 8 {
+                        return Err(FenError::RankLength {rank: 8 - row, index});
+                    }
+                    if row == 7 {
+                        return Err(FenError::RankCount(9));
+                    }
+                    row += 1;
+                    file = 0;
+                    continue;
+                }
+                _ => {
+                    let piece = piece_from_char(c).ok_or(FenError::InvalidChar {field: FenField::Placement, index, c})?;
+                    if file < 8 {
+                        state.set_piece(row * 8 + file, piece);
+                    }
+                    file += 1;
+                }
+            }
+            if file > 8 {
+                return Err(FenError::RankLength {rank: 8 - row, index});
             }
         }
-        state.set_castle(cs);
-    }
+        if row != 7 {
+            return Err(FenError::RankCount(row + 1));
+        }
+        if file != 8 {
+            return Err(FenError::RankLength {rank: 1, index: start + placement.len()});
+        }
 
-    state.set_en_passant(8, 8);
-    if parts[3] != "-" {
-        if parts[3].len() != 2 {
-            return Err(());
+        let (index, side) = fields[1];
+        match side {
+            "w" => {}
+            "b" => state.switch_turn(),
+            _ => return Err(FenError::InvalidField {field: FenField::SideToMove, index}),
         }
-        let file = match parts[3].chars().nth(0) {
-            Some(c) if c.is_ascii_lowercase() => c as usize - 'a' as usize,
-            _ => return Err(()),
-        };
-        let rank = match parts[3].chars().nth(1) {
-            Some(c) if c.is_ascii_digit() => 8 - (c.to_digit(10).unwrap() as usize),
-            _ => return Err(()),
-        };
-        if file > 7 || rank > 7 {
-            return Err(());
+
+        let (start, castling_field) = fields[2];
+        let mut castling = 0;
+        if castling_field != "-" {
+            for (i, c) in castling_field.char_indices() {
+                castling |= match c {
+                    'K' | 'H' => 1,
+                    'Q' | 'A' => 2,
+                    'k' | 'h' => 4,
+                    'q' | 'a' => 8,
+                    _ => return Err(FenError::InvalidChar {field: FenField::Castling, index: start + i, c}),
+                };
+            }
+        }
+        state.set_castle(castling);
+
+        let (ep_index, en_passant) = fields[3];
+        if en_passant != "-" {
+            let sq = parse_square(en_passant)
+                .filter(|_| en_passant.len() == 2)
+                .ok_or(FenError::InvalidField {field: FenField::EnPassant, index: ep_index})?;
+            state.set_en_passant(sq / 8, sq % 8);
         }
-        state.set_en_passant(rank, file);
-    }
 
-    if let Ok(halfmove_clock) = parts[4].parse::<u32>() {
-        state.halfmove_clock = halfmove_clock;
-    } else {
-        return Err(());
+        let (index, halfmove) = fields[4];
+        state.halfmove_clock = halfmove.parse::<u32>().ok()
+            .filter(|&n| n <= 150)
+            .ok_or(FenError::InvalidField {field: FenField::HalfmoveClock, index})?;
+        let (index, fullmove) = fields[5];
+        state.fullmove_number = fullmove.parse::<u32>().ok()
+            .filter(|&n| n >= 1)
+            .ok_or(FenError::InvalidField {field: FenField::FullmoveNumber, index})?;
+
+        state.validate(ep_index)?;
+        Ok(state)
     }
 
-    if let Ok(fullmove_number) = parts[5].parse::<u32>() {
-        state.fullmove_number = fullmove_number;
-    } else {
-        return Err(());
+    // Semantic checks of a parsed position, `ep_index` locates the en passant field
+    fn validate(&self, ep_index: usize) -> Result<(), FenError> {
+        for color in [PieceColor::White, PieceColor::Black] {
+            let count = self.pieces_of(PieceType::King, color).count_ones();
+            if count != 1 {
+                return Err(FenError::KingCount {color, count});
+            }
+        }
+
+        const BACK_RANKS: u64 = 0xFF | 0xFF << 56;
+        let pawns = self.pieces[PieceType::Pawn as usize] & BACK_RANKS;
+        if pawns != 0 {
+            return Err(FenError::PawnOnBackRank {square: pawns.trailing_zeros() as usize});
+        }
+
+        // (right, letter, king square, rook square, colour)
+        let rights = [
+            (1, 'K', 60, 63, PieceColor::White),
+            (2, 'Q', 60, 56, PieceColor::White),
+            (4, 'k', 4, 7, PieceColor::Black),
+            (8, 'q', 4, 0, PieceColor::Black),
+        ];
+        for (bit, right, king, rook, color) in rights {
+            if self.castling & bit != 0
+                && (self.board[king] != Some(Piece {color, tp: PieceType::King})
+                    || self.board[rook] != Some(Piece {color, tp: PieceType::Rook})) {
+                return Err(FenError::CastlingWithoutRook {right});
+            }
+        }
+
+        if self.en_passant != (8, 8) {
+            // the pawn that just moved two squares and where it came from
+            let (row, file) = self.en_passant;
+            let (expected_row, pushed, from) = if self.turn == PieceColor::White {(2, 3, 1)} else {(5, 4, 6)};
+            let pawn = Piece {color: !self.turn, tp: PieceType::Pawn};
+            if row != expected_row
+                || self.board[pushed * 8 + file] != Some(pawn)
+                || self.board[row * 8 + file].is_some()
+                || self.board[from * 8 + file].is_some() {
+                return Err(FenError::EnPassantImpossible {index: ep_index});
+            }
+        }
+
+        if king_attacked(!self.turn, self) {
+            return Err(FenError::OpponentInCheck);
+        }
+        Ok(())
     }
 
-    if state.halfmove_clock > 100 {
-        return Err(());
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for row in 0..8 {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.board[row * 8 + file] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece_to_char(piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if row < 7 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.turn == PieceColor::White {" w "} else {" b "});
+        let castling = "KQkq".chars().enumerate()
+            .filter(|&(i, _)| self.castling & (1 << i) != 0)
+            .map(|(_, c)| c)
+            .collect::<String>();
+        fen.push_str(if castling.is_empty() {"-"} else {&castling});
+
+        fen.push(' ');
+        if self.en_passant == (8, 8) {
+            fen.push('-');
+        } else {
+            fen.push_str(&square_name(self.en_passant.0 * 8 + self.en_passant.1));
+        }
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
     }
+}
 
-    return Ok(());
+// Everything make_move_on can't recover from the move itself
+#[derive(Clone, Copy)]
+pub struct Undo {
+    pub captured: Option<Piece>,
+    pub castling: u32,
+    pub en_passant: (usize, usize),
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    pub zob_hash: u64,
 }
 
-pub fn make_move_on(from: usize, to: usize, state: &mut State) {
-    if from >= 64 || to >= 64 || from == to {
-        return;
+// (from, to) of the rook that moves along with a castling king
+fn castling_rook(mv: Move) -> (usize, usize) {
+    match (mv.flags(), mv.from()) {
+        (KING_CASTLE, 60) => (63, 61),
+        (QUEEN_CASTLE, 60) => (56, 59),
+        (KING_CASTLE, _) => (7, 5),
+        _ => (0, 3),
     }
+}
+
+// Square of the pawn taken by an en passant capture
+fn en_passant_victim(mv: Move) -> usize {
+    if mv.to() < mv.from() {mv.to() + 8} else {mv.to() - 8}
+}
 
+// Plays a move generated for `state`. Anything else, such as Move::NULL, is a
+// bug in the caller and panics: a silently ignored move would hand back an Undo
+// that unmake_move can't take back.
+pub fn make_move_on(mv: Move, state: &mut State) -> Undo {
+    let (from, to) = (mv.from(), mv.to());
     let mut piece = match state.board[from] {
-        Some(p) if p.color == state.turn => p,
-        _ => return,
+        Some(p) if p.color == state.turn && from != to => p,
+        _ => panic!("make_move_on: {} is not a move for the side to move", mv.to_uci()),
+    };
+    let mut undo = Undo {
+        captured: state.board[to],
+        castling: state.castling,
+        en_passant: state.en_passant,
+        halfmove_clock: state.halfmove_clock,
+        fullmove_number: state.fullmove_number,
+        zob_hash: state.zob_hash,
     };
 
-    //en passant check
-    if piece.tp == PieceType::Pawn && state.en_passant.0 * 8 + state.en_passant.1 == to {
-        if piece.color == PieceColor::White {
-            state.remove_piece(to + 8); 
-        }
-        else {
-            state.remove_piece(to - 8); 
-        }
+    // pawn moves and captures can't be undone, so they restart the fifty-move count
+    if piece.tp == PieceType::Pawn || mv.is_capture() {
+        state.halfmove_clock = 0;
+    } else {
+        state.halfmove_clock += 1;
+    }
+    if piece.color == PieceColor::Black {
+        state.fullmove_number += 1;
     }
-
     state.set_en_passant(8, 8);
-    if piece.tp == PieceType::Pawn {
-        if from.abs_diff(to) == 16 {
-            state.set_en_passant(
-                if piece.color == PieceColor::White {from/8-1} else {from/8+1}, 
-                from%8
-            );
-        }
-    } 
+
+    //en passant check
+    if mv.is_en_passant() {
+        undo.captured = state.board[en_passant_victim(mv)];
+        state.remove_piece(en_passant_victim(mv));
+    }
 
     //Pawn promotion
-    if piece.tp == PieceType::Pawn {
-        if (piece.color == PieceColor::White && to < 8) || (piece.color == PieceColor::Black && to >= 56) {
-            piece = Piece {color: piece.color, tp: state.promotion};
-        }
+    if let Some(tp) = mv.promotion() {
+        piece = Piece {color: piece.color, tp};
     }
 
     //Castling check
     if piece.tp == PieceType::King {
-        if from == 60 && to == 62 { // White kingside castling
-            if (state.castling & 1) == 1 {
-                state.set_piece(61, Piece {color: PieceColor::White, tp: PieceType::Rook});
-                state.remove_piece(63);
-            }
-        } else if from == 60 && to == 58 { // White queenside castling
-            if (state.castling & 2) == 2 {
-                state.set_piece(59, Piece {color: PieceColor::White, tp: PieceType::Rook});
-                state.remove_piece(56);
-            }
-        } else if from == 4 && to == 6 { // Black kingside castling
-            if (state.castling & 4) == 4{
-                state.set_piece(5, Piece {color: PieceColor::Black, tp: PieceType::Rook});
-                state.remove_piece(7);
-            }
-        } else if from == 4 && to == 2 { // Black queenside castling
-            if (state.castling&8) == 8 {
-                state.set_piece(3,Piece {color: PieceColor::Black, tp: PieceType::Rook});
-                state.remove_piece(0);
-            }
-        } 
+        if mv.is_castle() {
+            let (rook_from, rook_to) = castling_rook(mv);
+            state.set_piece(rook_to, Piece {color: piece.color, tp: PieceType::Rook});
+            state.remove_piece(rook_from);
+        }
         if piece.color == PieceColor::White {
             state.set_castle(state.castling & 3u32.not());
         } else {
@@ -300,33 +627,193 @@ pub fn make_move_on(from: usize, to: usize, state: &mut State) {
         state.set_castle(state.castling & 8u32.not()); 
     }
 
-
-
     state.set_piece(to, piece);
     state.remove_piece(from);
 
     state.switch_turn();
+    // set once the pawn has arrived and the opponent is to move
+    if mv.flags() == DOUBLE_PUSH {
+        state.set_en_passant(if piece.color == PieceColor::White {from/8-1} else {from/8+1}, from%8);
+    }
+    undo
+}
+
+// Takes back a move played with make_move_on, given the Undo it returned. Like
+// make_move_on it panics on a move that wasn't just played, before touching `state`.
+pub fn unmake_move(mv: Move, undo: &Undo, state: &mut State) {
+    let (from, to) = (mv.from(), mv.to());
+    let mut piece = match state.board[to] {
+        Some(p) if p.color != state.turn && from != to => p,
+        _ => panic!("unmake_move: {} is not the last move played", mv.to_uci()),
+    };
+    state.switch_turn();
+    if mv.is_promotion() {
+        piece.tp = PieceType::Pawn;
+    }
+    state.remove_piece(to);
+    state.set_piece(from, piece);
+
+    if let Some(captured) = undo.captured {
+        state.set_piece(if mv.is_en_passant() {en_passant_victim(mv)} else {to}, captured);
+    }
+    if mv.is_castle() {
+        let (rook_from, rook_to) = castling_rook(mv);
+        state.remove_piece(rook_to);
+        state.set_piece(rook_from, Piece {color: piece.color, tp: PieceType::Rook});
+    }
+
+    state.set_castle(undo.castling);
+    state.set_en_passant(undo.en_passant.0, undo.en_passant.1);
+    state.halfmove_clock = undo.halfmove_clock;
+    state.fullmove_number = undo.fullmove_number;
+    state.zob_hash = undo.zob_hash;
+}
+
+pub fn is_square_attacked(sq: usize, by: PieceColor, state: &State) -> bool {
+    let occ = state.occupied;
+    let queens = state.pieces_of(PieceType::Queen, by);
+
+    PAWN_ATTACKS[(!by) as usize][sq] & state.pieces_of(PieceType::Pawn, by) != 0
+        || KNIGHT_ATTACKS[sq] & state.pieces_of(PieceType::Knight, by) != 0
+        || KING_ATTACKS[sq] & state.pieces_of(PieceType::King, by) != 0
+        || bishop_attacks(sq, occ) & (state.pieces_of(PieceType::Bishop, by) | queens) != 0
+        || rook_attacks(sq, occ) & (state.pieces_of(PieceType::Rook, by) | queens) != 0
+}
+
+pub fn king_attacked(color: PieceColor, state: &State) -> bool {
+    let king = state.pieces_of(PieceType::King, color);
+    king != 0 && is_square_attacked(king.trailing_zeros() as usize, !color, state)
 }
 
 pub fn in_check_state(state: & State) -> bool {
-    for i in 0..64 {
-        match state.board[i] {
-            Some(piece) if piece.color != state.turn => {
-                for &to in &get_valid_moves_for_raw(i, state) {
-                    if let Some(target_piece) = state.board[to] {
-                        if target_piece.tp == PieceType::King && target_piece.color == state.turn {
-                            return true
-                        }
-                    }
+    king_attacked(state.turn, state)
+}
+
+// Positions are compared by zobrist hash. `history` holds the hashes of the
+// positions before `state` in the game, oldest first.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate,
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    SeventyFiveMoveRule,
+    InsufficientMaterial,
+}
+
+// How many times the current position has occurred, counting itself
+pub fn repetition_count(state: &State, history: &[u64]) -> usize {
+    1 + history.iter().filter(|&&hash| hash == state.zob_hash).count()
+}
+
+// Neither side can possibly mate: K v K, K+minor v K, or bishops that all share a square colour
+pub fn is_insufficient_material(state: &State) -> bool {
+    let heavy = state.pieces[PieceType::Pawn as usize]
+        | state.pieces[PieceType::Rook as usize]
+        | state.pieces[PieceType::Queen as usize];
+    if heavy != 0 {
+        return false;
+    }
+    let knights = state.pieces[PieceType::Knight as usize];
+    let bishops = state.pieces[PieceType::Bishop as usize];
+    let minors = (knights | bishops).count_ones();
+    if minors <= 1 {
+        return true;
+    }
+    // a8, c8, ..., h1: the squares whose row + file is even
+    const LIGHT_SQUARES: u64 = 0xAA55_AA55_AA55_AA55;
+    knights == 0 && (bishops & LIGHT_SQUARES == 0 || bishops & !LIGHT_SQUARES == 0)
+}
+
+pub fn game_status(state: &State, history: &[u64]) -> GameStatus {
+    if get_all_valid_moves(state).is_empty() {
+        return if in_check_state(state) {GameStatus::Checkmate} else {GameStatus::Stalemate};
+    }
+    if state.halfmove_clock >= 150 {
+        GameStatus::SeventyFiveMoveRule
+    } else if is_insufficient_material(state) {
+        GameStatus::InsufficientMaterial
+    } else if repetition_count(state, history) >= 3 {
+        GameStatus::ThreefoldRepetition
+    } else if state.halfmove_clock >= 100 {
+        GameStatus::FiftyMoveRule
+    } else {
+        GameStatus::Ongoing
+    }
+}
+
+// Castling needs the rights bit, the rook still in its corner, empty squares
+// between king and rook, and the king not in check nor passing through or
+// landing on an attacked square.
+fn castling_targets(from: usize, color: PieceColor, state: &State) -> u64 {
+    let (king_sq, rights, back_rank) = if color == PieceColor::White {(60, state.castling & 3, 56)} else {(4, state.castling >> 2 & 3, 0)};
+    if from != king_sq || rights == 0 || is_square_attacked(king_sq, !color, state) {
+        return 0;
+    }
+    let rook = Some(Piece {color, tp: PieceType::Rook});
+
+    let mut targets = 0;
+    // kingside: f and g files empty and safe
+    if rights & 1 == 1 && state.board[back_rank + 7] == rook
+        && state.occupied & (bit(back_rank + 5) | bit(back_rank + 6)) == 0
+        && !is_square_attacked(back_rank + 5, !color, state)
+        && !is_square_attacked(back_rank + 6, !color, state) {
+        targets |= bit(back_rank + 6);
+    }
+    // queenside: b, c and d files empty, only c and d need to be safe
+    if rights & 2 == 2 && state.board[back_rank] == rook
+        && state.occupied & (bit(back_rank + 1) | bit(back_rank + 2) | bit(back_rank + 3)) == 0
+        && !is_square_attacked(back_rank + 3, !color, state)
+        && !is_square_attacked(back_rank + 2, !color, state) {
+        targets |= bit(back_rank + 2);
+    }
+    targets
+}
+
+fn pawn_targets(from: usize, color: PieceColor, state: &State) -> u64 {
+    let en_p = if state.en_passant.1 != 8 {bit(state.en_passant.0 * 8 + state.en_passant.1)} else {0};
+    let mut targets = PAWN_ATTACKS[color as usize][from] & (state.colors[(!color) as usize] | en_p);
+
+    let empty = !state.occupied;
+    if color == PieceColor::White {
+        if from >= 8 && empty & bit(from - 8) != 0 {
+            targets |= bit(from - 8);
+            if from / 8 == 6 && empty & bit(from - 16) != 0 {
+                targets |= bit(from - 16);
+            }
+        }
+    } else if from < 56 && empty & bit(from + 8) != 0 {
+        targets |= bit(from + 8);
+        if from / 8 == 1 && empty & bit(from + 16) != 0 {
+            targets |= bit(from + 16);
+        }
+    }
+    targets
+}
+
+// Turns a set of destination squares into moves with the right flags
+fn push_moves(from: usize, targets: u64, piece: Piece, state: &State, moves: &mut Vec<Move>) {
+    let en_p = state.en_passant.0 * 8 + state.en_passant.1;
+    for to in squares(targets) {
+        let capture = if state.board[to].is_some() {CAPTURE} else {QUIET};
+        match piece.tp {
+            PieceType::Pawn if !(8..56).contains(&to) => {
+                for tp in [PieceType::Queen, PieceType::Knight, PieceType::Rook, PieceType::Bishop] {
+                    moves.push(Move::new(from, to, promotion_flag(tp) | capture));
                 }
             }
-            _ => continue,
+            PieceType::Pawn if to == en_p => moves.push(Move::new(from, to, EN_PASSANT)),
+            PieceType::Pawn if from.abs_diff(to) == 16 => moves.push(Move::new(from, to, DOUBLE_PUSH)),
+            PieceType::King if from.abs_diff(to) == 2 => {
+                moves.push(Move::new(from, to, if to > from {KING_CASTLE} else {QUEEN_CASTLE}));
+            }
+            _ => moves.push(Move::new(from, to, capture)),
         }
     }
-    false
 }
 
-pub fn get_valid_moves_for_raw(from: usize, state: &State) -> Vec<usize> {
+pub fn get_valid_moves_for_raw(from: usize, state: &State) -> Vec<Move> {
     if from >= 64 {
         return Vec::new();
     }
@@ -336,187 +823,497 @@ pub fn get_valid_moves_for_raw(from: usize, state: &State) -> Vec<usize> {
         None => return Vec::new(),
     };
 
+    let occ = state.occupied;
+    let targets = match piece.tp {
+        PieceType::Knight => KNIGHT_ATTACKS[from],
+        PieceType::Bishop => bishop_attacks(from, occ),
+        PieceType::Rook => rook_attacks(from, occ),
+        PieceType::Queen => queen_attacks(from, occ),
+        PieceType::King => KING_ATTACKS[from] | castling_targets(from, piece.color, state),
+        PieceType::Pawn => pawn_targets(from, piece.color, state),
+    };
+
+    let mut moves = Vec::new();
+    push_moves(from, targets & !state.colors[piece.color as usize], piece, state, &mut moves);
+    moves
+}
+
+// Captures (including en passant) and promotions only, for quiescence search
+pub fn get_captures_for_raw(from: usize, state: &State) -> Vec<Move> {
+    get_valid_moves_for_raw(from, state)
+        .into_iter()
+        .filter(|mv| mv.is_capture() || mv.is_promotion())
+        .collect()
+}
+
+pub fn get_valid_moves_for(from: usize, state: &State) -> Vec<Move> {
+    if from >= 64 {
+        return Vec::new();
+    }
+
+    let _piece = match state.board[from] {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+
     let mut valid_moves = Vec::new();
 
-    match piece.tp {
-        PieceType::Rook | PieceType::Bishop | PieceType::Queen => {
+    // Get all potential moves for the piece
+    let potential_moves = get_valid_moves_for_raw(from, state);
 
-            let dir = if piece.tp == PieceType::Rook {
-                vec![(-1, 0), (1, 0), (0, -1), (0, 1)] 
-            } else if piece.tp == PieceType::Bishop {
-                vec![(-1, -1), (-1, 1), (1, -1), (1, 1)]
-            } else {
-                vec![(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (-1, 1), (1, -1), (1, 1)]
-            };
+    // Filter out moves that would leave the king in check
+    let mut temp_state = state.clone();
+    for &mv in &potential_moves {
+        let undo = make_move_on(mv, &mut temp_state);
+        if !king_attacked(state.turn, &temp_state) {
+            valid_moves.push(mv);
+        }
+        unmake_move(mv, &undo, &mut temp_state);
+    }
 
-            for (dx, dy) in dir {
-                let mut x = from as i16 % 8;
-                let mut y = from as i16 / 8;
+    valid_moves
+}
 
-                loop {
-                    x += dx;
-                    y += dy;
+pub fn get_all_valid_moves_raw(state: &State) -> Vec<Move> {
+    let mut res = Vec::new();
+    for i in squares(state.colors[state.turn as usize]) {
+        res.extend(get_valid_moves_for_raw(i, state));
+    }
+    res
+}
 
-                    if x < 0 || x >= 8 || y < 0 || y >= 8 {
-                        break;
-                    }
+pub fn get_all_valid_moves(state: &State) -> Vec<Move> {
+    let mut res = Vec::new();
+    for i in squares(state.colors[state.turn as usize]) {
+        res.extend(get_valid_moves_for(i, state));
+    }
+    res
+}
 
-                    let pos = (y * 8 + x) as usize;
+pub fn get_all_captures_raw(state: &State) -> Vec<Move> {
+    let mut res = Vec::new();
+    for i in squares(state.colors[state.turn as usize]) {
+        res.extend(get_captures_for_raw(i, state));
+    }
+    res
+}
 
-                    match state.board[pos] {
-                        Some(p) if p.color != piece.color => {
-                            valid_moves.push(pos);
-                            break;
-                        }
-                        None => valid_moves.push(pos),
-                        _ => break,
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The bitboards must describe exactly what the `board` mailbox holds
+    fn assert_bitboards_match(state: &State) {
+        for sq in 0..64 {
+            let piece = state.board[sq];
+            assert_eq!(state.occupied & bit(sq) != 0, piece.is_some(), "occupied {}", sq);
+            for color in [PieceColor::White, PieceColor::Black] {
+                assert_eq!(state.colors[color as usize] & bit(sq) != 0, piece.is_some_and(|p| p.color == color), "color {}", sq);
+            }
+            for tp in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King] {
+                assert_eq!(state.pieces[tp as usize] & bit(sq) != 0, piece.is_some_and(|p| p.tp == tp), "piece {}", sq);
             }
         }
+    }
 
-        PieceType::Knight => {
-            let knight_moves = vec![
-                (-2, -1), (-1, -2), (1, -2), (2, -1),
-                (2, 1), (1, 2), (-1, 2), (-2, 1)
-            ];
-            for (dx, dy) in knight_moves {
-                let x = from as i16 % 8 + dx;
-                let y = from as i16 / 8 + dy;
-
-                if x >= 0 && x < 8 && y >= 0 && y < 8 {
-                    let pos = (y * 8 + x) as usize;
-                    match state.board[pos] {
-                        Some(p) if p.color != piece.color => valid_moves.push(pos),
-                        None => valid_moves.push(pos),
-                        _ => {}
-                    }
-                }
+    #[test]
+    fn move_packing_round_trips() {
+        for (from, to) in [(0, 63), (63, 0), (52, 36), (12, 4), (8, 1)] {
+            for flags in [QUIET, DOUBLE_PUSH, KING_CASTLE, QUEEN_CASTLE, CAPTURE, EN_PASSANT] {
+                let mv = Move::new(from, to, flags);
+                assert_eq!((mv.from(), mv.to(), mv.flags()), (from, to, flags));
+                assert!(mv.promotion().is_none());
             }
         }
-
-        PieceType::King => {
-            let king_moves = vec![
-                (-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)
-            ];
-            for (dx, dy) in king_moves {
-                let x = from as i16 % 8 + dx;
-                let y = from as i16 / 8 + dy;
-
-                if x >= 0 && x < 8 && y >= 0 && y < 8 {
-                    let pos = (y * 8 + x) as usize;
-                    match state.board[pos] {
-                        Some(p) if p.color != piece.color => valid_moves.push(pos),
-                        None => valid_moves.push(pos),
-                        _ => {}
-                    }
-                }
+        assert!(Move::new(60, 62, KING_CASTLE).is_castle() && Move::new(4, 2, QUEEN_CASTLE).is_castle());
+        assert!(Move::new(27, 20, EN_PASSANT).is_en_passant() && Move::new(27, 20, EN_PASSANT).is_capture());
+        assert!(!Move::new(52, 36, DOUBLE_PUSH).is_capture());
+
+        // every promotion piece, with and without a capture
+        for tp in [PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen] {
+            for capture in [0, CAPTURE] {
+                let mv = Move::new(9, 0, promotion_flag(tp) | capture);
+                assert_eq!((mv.from(), mv.to()), (9, 0));
+                assert!(mv.is_promotion());
+                assert!(mv.promotion() == Some(tp));
+                assert_eq!(mv.is_capture(), capture != 0);
+                assert!(!mv.is_en_passant() && !mv.is_castle());
             }
+        }
+        assert_eq!(Move::new(9, 0, promotion_flag(PieceType::Knight) | CAPTURE).to_uci(), "b7a8n");
+        assert_eq!(Move::NULL, Move::new(0, 0, QUIET));
+    }
 
-            // Castling
-            if piece.color == PieceColor::White {
-                if (state.castling&1)==1 && state.board[61].is_none() && state.board[62].is_none() {
-                    valid_moves.push(62); // White kingside castling
-                }
-                if state.castling&2 == 2 && state.board[59].is_none() && state.board[58].is_none() && state.board[57].is_none() {
-                    valid_moves.push(58); // White queenside castling
-                }
-            } else {
-                if state.castling&4 == 4 && state.board[5].is_none() && state.board[6].is_none() {
-                    valid_moves.push(6); // Black kingside castling
-                }
-                if state.castling&8 == 8 && state.board[3].is_none() && state.board[2].is_none() && state.board[1].is_none() {
-                    valid_moves.push(2); // Black queenside castling
-                }
+    #[test]
+    fn unmake_restores_everything() {
+        let mut special = Vec::new();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 3 12",
+            "r3k2r/1P4p1/8/2Pp4/8/8/6p1/R3K2R w KQkq d6 0 30",
+            "r3k2r/1P4p1/8/8/2pP4/8/6p1/R3K2R b KQkq d3 5 30",
+        ] {
+            let mut state = State::from_fen(fen).unwrap();
+            let moves = get_all_valid_moves(&state);
+            for mv in moves {
+                special.push(mv.flags());
+                let undo = make_move_on(mv, &mut state);
+                unmake_move(mv, &undo, &mut state);
+                assert_eq!(state.to_fen(), fen, "{}", mv.to_uci());
+                assert_eq!(state.zob_hash, State::from_fen(fen).unwrap().zob_hash, "{}", mv.to_uci());
+                assert_eq!(state.castling, 15);
+                assert_eq!(state.occupied, State::from_fen(fen).unwrap().occupied);
             }
         }
+        for flags in [KING_CASTLE, QUEEN_CASTLE, CAPTURE, EN_PASSANT, DOUBLE_PUSH, promotion_flag(PieceType::Knight) | CAPTURE] {
+            assert!(special.contains(&flags), "no move with flags {}", flags);
+        }
+    }
 
-        PieceType::Pawn => {
-            let en_p = state.en_passant.0 * 8 + state.en_passant.1;
-            if piece.color == PieceColor::White {
-                if from >= 8 && state.board[from - 8].is_none() {
-                    valid_moves.push(from - 8);
-                    if from / 8 == 6 && state.board[from - 16].is_none() {
-                        valid_moves.push(from - 16);
-                    }
-                }
-                if from % 8 > 0 && from >= 8 && state.board[from - 9].map_or(from-9 == en_p, |p| p.color == PieceColor::Black) {
-                    valid_moves.push(from - 9);
-                }
-                if from % 8 < 7 && from >= 8 && state.board[from - 7].map_or(from-7 == en_p, |p| p.color == PieceColor::Black) {
-                    valid_moves.push(from - 7);
-                }
-            } else {
-                if from < 56 && state.board[from + 8].is_none() {
-                    valid_moves.push(from + 8);
-                    if from / 8 == 1 && state.board[from + 16].is_none() {
-                        valid_moves.push(from + 16);
-                    }
-                }
-                if from % 8 > 0 && from < 56 && state.board[from + 7].map_or(from+7 == en_p, |p| p.color == PieceColor::White) {
-                    valid_moves.push(from + 7);
-                }
-                if from % 8 < 7 && from < 56 && state.board[from + 9].map_or(from+9 == en_p, |p| p.color == PieceColor::White) {
-                    valid_moves.push(from + 9);
+    #[test]
+    #[should_panic(expected = "not a move for the side to move")]
+    fn null_move_is_rejected() {
+        let mut state = State::from_fen(START_FEN).unwrap();
+        make_move_on(Move::NULL, &mut state);
+    }
+
+    #[test]
+    fn unmaking_another_move_leaves_the_state_alone() {
+        let mut state = State::from_fen(START_FEN).unwrap();
+        let undo = make_move_on(parse_uci_move(&state, "e2e4").unwrap(), &mut state);
+        let fen = state.to_fen();
+        // nothing stands on d4, and the pawn on a7 belongs to the side to move
+        for mv in [Move::new(51, 35, DOUBLE_PUSH), Move::NULL, Move::new(16, 8, QUIET)] {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unmake_move(mv, &undo, &mut state)));
+            assert!(result.is_err(), "{}", mv.to_uci());
+            assert_eq!(state.to_fen(), fen);
+        }
+        unmake_move(Move::new(52, 36, DOUBLE_PUSH), &undo, &mut state);
+        assert_eq!(state.to_fen(), START_FEN);
+    }
+
+    #[test]
+    fn bitboards_follow_the_board() {
+        let mut state = State::new();
+        assert_bitboards_match(&state);
+        state.set_piece(0, Piece {color: PieceColor::Black, tp: PieceType::Rook});
+        state.set_piece(63, Piece {color: PieceColor::White, tp: PieceType::Queen});
+        assert_bitboards_match(&state);
+        // replacing a piece clears the old one from its bitboards
+        state.set_piece(0, Piece {color: PieceColor::White, tp: PieceType::Knight});
+        assert_bitboards_match(&state);
+        assert_eq!(state.pieces_of(PieceType::Rook, PieceColor::Black), 0);
+        state.remove_piece(63);
+        state.remove_piece(62);
+        assert_bitboards_match(&state);
+        assert_eq!(state.occupied, bit(0));
+
+        let mut state = State::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_bitboards_match(&state);
+        for mv in get_all_valid_moves(&state) {
+            let undo = make_move_on(mv, &mut state);
+            assert_bitboards_match(&state);
+            unmake_move(mv, &undo, &mut state);
+        }
+        assert_bitboards_match(&state);
+    }
+
+    fn castles(fen: &str) -> Vec<usize> {
+        let state = State::from_fen(fen).unwrap();
+        get_all_valid_moves(&state).iter().filter(|mv| mv.is_castle()).map(|mv| mv.to()).collect()
+    }
+
+    #[test]
+    fn castling_both_sides_when_free() {
+        assert_eq!(castles("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"), vec![58, 62]);
+        assert_eq!(castles("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1"), vec![2, 6]);
+    }
+
+    #[test]
+    fn no_castling_out_of_check() {
+        assert!(castles("r3k2r/8/8/8/4r3/8/8/R3K2R w KQkq - 0 1").is_empty());
+        assert!(castles("r3k2r/8/8/4R3/8/8/8/R3K2R b KQkq - 0 1").is_empty());
+    }
+
+    #[test]
+    fn no_castling_through_check() {
+        // f1 and d1 attacked
+        assert_eq!(castles("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1"), vec![58]);
+        assert_eq!(castles("3rk3/8/8/8/8/8/8/R3K2R w KQ - 0 1"), vec![62]);
+        // f8 and d8 attacked
+        assert_eq!(castles("r3k2r/8/8/8/8/8/8/4KR2 b kq - 0 1"), vec![2]);
+        assert_eq!(castles("r3k2r/8/8/8/8/8/8/3RK3 b kq - 0 1"), vec![6]);
+    }
+
+    #[test]
+    fn no_castling_into_check() {
+        assert_eq!(castles("4k1r1/8/8/8/8/8/8/R3K2R w KQ - 0 1"), vec![58]);
+        assert_eq!(castles("2r1k3/8/8/8/8/8/8/R3K2R w KQ - 0 1"), vec![62]);
+    }
+
+    #[test]
+    fn queenside_castling_allowed_with_b_file_attacked() {
+        assert_eq!(castles("1r2k3/8/8/8/8/8/8/R3K2R w KQ - 0 1"), vec![58, 62]);
+    }
+
+    #[test]
+    fn no_castling_without_rook_in_corner() {
+        // such positions are rejected by from_fen, so take the rook away afterwards
+        let castles_without = |replace: &[(usize, Option<Piece>)]| {
+            let mut state = State::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+            for &(sq, piece) in replace {
+                state.remove_piece(sq);
+                if let Some(piece) = piece {
+                    state.set_piece(sq, piece);
                 }
             }
-        }
+            get_all_valid_moves(&state).iter().filter(|mv| mv.is_castle()).map(|mv| mv.to()).collect::<Vec<usize>>()
+        };
+        assert_eq!(castles_without(&[(56, None)]), vec![62]);
+        assert_eq!(castles_without(&[(63, None)]), vec![58]);
+        // rook of the wrong colour
+        let knight = Some(Piece {color: PieceColor::Black, tp: PieceType::Knight});
+        assert!(castles_without(&[(56, knight), (63, knight)]).is_empty());
     }
 
-    valid_moves
-}
+    fn status(fen: &str) -> GameStatus {
+        let state = State::from_fen(fen).unwrap();
+        game_status(&state, &[])
+    }
 
-pub fn get_valid_moves_for(from: usize, state: &State) -> Vec<usize> {
-    if from >= 64 {
-        return Vec::new();
+    #[test]
+    fn checkmate_and_stalemate() {
+        assert_eq!(status("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"), GameStatus::Checkmate);
+        assert_eq!(status("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), GameStatus::Stalemate);
+        assert_eq!(status("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), GameStatus::Ongoing);
     }
 
-    let _piece = match state.board[from] {
-        Some(p) => p,
-        None => return Vec::new(),
-    };
+    #[test]
+    fn insufficient_material() {
+        assert_eq!(status("8/8/4k3/8/8/3K4/8/8 w - - 0 1"), GameStatus::InsufficientMaterial);
+        assert_eq!(status("8/8/4k3/8/8/3KN3/8/8 w - - 0 1"), GameStatus::InsufficientMaterial);
+        assert_eq!(status("8/8/4k3/8/8/3KB3/8/8 b - - 0 1"), GameStatus::InsufficientMaterial);
+        // bishops on c1 and f8 are both on dark squares
+        assert_eq!(status("5b2/8/4k3/8/8/3K4/8/2B5 w - - 0 1"), GameStatus::InsufficientMaterial);
+        // opposite coloured bishops, two knights and any pawn can still mate
+        assert_eq!(status("2b5/8/4k3/8/8/3K4/8/2B5 w - - 0 1"), GameStatus::Ongoing);
+        assert_eq!(status("8/8/4k3/8/8/3KNN2/8/8 w - - 0 1"), GameStatus::Ongoing);
+        assert_eq!(status("8/8/4k3/8/8/3K4/4P3/8 w - - 0 1"), GameStatus::Ongoing);
+    }
 
-    let mut valid_moves = Vec::new();
+    #[test]
+    fn move_rules() {
+        assert_eq!(status("8/8/4k3/8/8/3K4/8/R7 w - - 99 80"), GameStatus::Ongoing);
+        assert_eq!(status("8/8/4k3/8/8/3K4/8/R7 w - - 100 80"), GameStatus::FiftyMoveRule);
+        assert_eq!(status("8/8/4k3/8/8/3K4/8/R7 w - - 150 80"), GameStatus::SeventyFiveMoveRule);
+        // mate on the last move before the limit still counts
+        assert_eq!(status("k7/1Q6/1K6/8/8/8/8/8 b - - 100 80"), GameStatus::Checkmate);
+    }
 
-    // Get all potential moves for the piece
-    let potential_moves = get_valid_moves_for_raw(from, state);
+    #[test]
+    fn move_counters_follow_known_game() {
+        // 1. e4 c5 2. Nf3 Nc6 3. Bb5 g6 4. O-O Bg7 5. Bxc6 dxc6
+        let game = [
+            ("e2e4", "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"),
+            ("c7c5", "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2"),
+            ("g1f3", "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"),
+            ("b8c6", "r1bqkbnr/pp1ppppp/2n5/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"),
+            ("f1b5", "r1bqkbnr/pp1ppppp/2n5/1Bp5/4P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3"),
+            ("g7g6", "r1bqkbnr/pp1ppp1p/2n3p1/1Bp5/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4"),
+            ("e1g1", "r1bqkbnr/pp1ppp1p/2n3p1/1Bp5/4P3/5N2/PPPP1PPP/RNBQ1RK1 b kq - 1 4"),
+            ("f8g7", "r1bqk1nr/pp1pppbp/2n3p1/1Bp5/4P3/5N2/PPPP1PPP/RNBQ1RK1 w kq - 2 5"),
+            ("b5c6", "r1bqk1nr/pp1pppbp/2B3p1/2p5/4P3/5N2/PPPP1PPP/RNBQ1RK1 b kq - 0 5"),
+            ("d7c6", "r1bqk1nr/pp2ppbp/2p3p1/2p5/4P3/5N2/PPPP1PPP/RNBQ1RK1 w kq - 0 6"),
+        ];
+        let mut state = State::from_fen(START_FEN).unwrap();
+        let mut played = Vec::new();
+        for (uci, fen) in game {
+            let mv = parse_uci_move(&state, uci).unwrap();
+            let before = state.to_fen();
+            played.push((mv, make_move_on(mv, &mut state), before));
+            assert_eq!(state.to_fen(), fen, "after {}", uci);
+        }
+        for (mv, undo, before) in played.iter().rev() {
+            unmake_move(*mv, undo, &mut state);
+            assert_eq!(&state.to_fen(), before);
+        }
+        assert_eq!(state.to_fen(), START_FEN);
+    }
 
-    // Filter out moves that would leave the king in check
-    for &to in &potential_moves {
-        let mut temp_state = state.clone();
-        make_move_on(from, to, &mut temp_state);
-        temp_state.switch_turn();
+    #[test]
+    fn en_passant_and_promotion_reset_the_clock() {
+        let mut state = State::from_fen("4k3/1P6/8/3pP3/8/8/8/4K3 w - d6 7 40").unwrap();
+        for (uci, fen) in [
+            ("e5d6", "4k3/1P6/3P4/8/8/8/8/4K3 b - - 0 40"),
+            ("e8d7", "8/1P1k4/3P4/8/8/8/8/4K3 w - - 1 41"),
+            ("b7b8q", "1Q6/3k4/3P4/8/8/8/8/4K3 b - - 0 41"),
+        ] {
+            make_move_on(parse_uci_move(&state, uci).unwrap(), &mut state);
+            assert_eq!(state.to_fen(), fen, "after {}", uci);
+        }
+    }
+
+    #[test]
+    fn fifty_moves_without_progress() {
+        let mut state = State::from_fen("8/8/4k3/8/8/3K4/8/R7 w - - 98 80").unwrap();
+        make_move_on(parse_uci_move(&state, "a1a2").unwrap(), &mut state);
+        assert_eq!(game_status(&state, &[]), GameStatus::Ongoing);
+        make_move_on(parse_uci_move(&state, "e6e5").unwrap(), &mut state);
+        assert_eq!(state.to_fen(), "8/8/8/4k3/8/3K4/R7/8 w - - 100 81");
+        assert_eq!(game_status(&state, &[]), GameStatus::FiftyMoveRule);
+    }
 
-        if !in_check_state(&temp_state) {
-            valid_moves.push(to);
+    #[test]
+    fn threefold_repetition() {
+        let mut state = State::from_fen(START_FEN).unwrap();
+        let mut history = Vec::new();
+        // Nf3 Nf6 Ng1 Ng8, twice
+        let shuffle = [Move::new(62, 45, QUIET), Move::new(6, 21, QUIET), Move::new(45, 62, QUIET), Move::new(21, 6, QUIET)];
+        for (i, &mv) in shuffle.iter().chain(shuffle.iter()).enumerate() {
+            assert_eq!(game_status(&state, &history), GameStatus::Ongoing, "after {} moves", i);
+            history.push(state.zob_hash);
+            make_move_on(mv, &mut state);
         }
+        assert_eq!(repetition_count(&state, &history), 3);
+        assert_eq!(game_status(&state, &history), GameStatus::ThreefoldRepetition);
     }
 
-    valid_moves
-}
+    #[test]
+    fn en_passant_only_counts_when_possible() {
+        // after 1.e4 e5 no white pawn can take on e6, so the position recurs after Nf3 Nf6 Ng1 Ng8
+        let mut state = State::from_fen(START_FEN).unwrap();
+        let mut history = Vec::new();
+        for uci in ["e2e4", "e7e5", "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8"] {
+            assert_eq!(game_status(&state, &history), GameStatus::Ongoing, "before {}", uci);
+            history.push(state.zob_hash);
+            make_move_on(parse_uci_move(&state, uci).unwrap(), &mut state);
+        }
+        assert_eq!(repetition_count(&state, &history), 3);
+        assert_eq!(game_status(&state, &history), GameStatus::ThreefoldRepetition);
+
+        let hash = |fen: &str| State::from_fen(fen).unwrap().zob_hash;
+        assert_eq!(hash("4k3/8/8/4p3/8/8/8/4K3 w - e6 0 2"), hash("4k3/8/8/4p3/8/8/8/4K3 w - - 0 2"));
+        assert!(hash("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 2") != hash("4k3/8/8/3Pp3/8/8/8/4K3 w - - 0 2"));
+
+        // the hash made move by move matches the one read from the FEN
+        let mut state = State::from_fen("4k3/3p4/8/4P3/8/8/8/4K3 b - - 0 1").unwrap();
+        make_move_on(parse_uci_move(&state, "d7d5").unwrap(), &mut state);
+        assert_eq!(state.zob_hash, hash("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2"));
+        make_move_on(parse_uci_move(&state, "e5d6").unwrap(), &mut state);
+        assert_eq!(state.zob_hash, hash("4k3/8/3P4/8/8/8/8/4K3 b - - 0 2"));
+    }
 
-pub fn get_all_valid_moves_raw(state: &State) -> Vec<(usize, usize)> {
-    let mut res = Vec::new();
-    for i in 0..64 {
-        if let Some(p) = state.board[i] {
-            if p.color != state.turn {continue;}
+    #[test]
+    fn uci_moves() {
+        let state = State::from_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        for (text, expected) in [("e1g1", "e1g1"), ("e1h1", "e1g1"), ("e1c1", "e1c1"), ("e1a1", "e1c1"),
+                                 ("b7b8q", "b7b8q"), ("b7a8n", "b7a8n"), ("a1a8", "a1a8")] {
+            assert_eq!(parse_uci_move(&state, text).map(|mv| mv.to_uci()), Some(expected.to_string()), "{}", text);
+        }
+        assert!(parse_uci_move(&state, "e1h1").unwrap().is_castle());
+        // promotions need the piece, other moves must not have one
+        for text in ["b7b8", "b7b8k", "e1f1q", "e8g8", "e1e3", "0000", "", "e1", "e1g1 ", "i1g1"] {
+            assert_eq!(parse_uci_move(&state, text), None, "{}", text);
         }
-        for &to in get_valid_moves_for_raw(i, state).iter() {
-            res.push((i, to));
+        for mv in get_all_valid_moves(&state) {
+            assert_eq!(parse_uci_move(&state, &mv.to_uci()), Some(mv));
         }
     }
-    res
-}
 
-pub fn get_all_valid_moves(state: &State) -> Vec<(usize, usize)> {
-    let mut res = Vec::new();
-    for i in 0..64 {
-        if let Some(p) = state.board[i] {
-            if p.color != state.turn {continue;}
+    // Plays pseudo-random games and checks that every position survives
+    // to_fen -> from_fen -> to_fen unchanged, hash included
+    #[test]
+    fn fen_round_trip() {
+        let starts = [
+            START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        for fen in starts {
+            for _ in 0..20 {
+                let mut state = State::from_fen(fen).unwrap();
+                for _ in 0..60 {
+                    let fen = state.to_fen();
+                    let parsed = State::from_fen(&fen).unwrap();
+                    assert_eq!(parsed.to_fen(), fen);
+                    assert_eq!(parsed.zob_hash, state.zob_hash, "{}", fen);
+                    assert_eq!(parsed.occupied, state.occupied, "{}", fen);
+
+                    let moves = get_all_valid_moves(&state);
+                    if moves.is_empty() {break;}
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    make_move_on(moves[seed as usize % moves.len()], &mut state);
+                }
+            }
         }
-        for &to in get_valid_moves_for(i, state).iter() {
-            res.push((i, to));
+    }
+
+    #[test]
+    fn shredder_castling_letters() {
+        let standard = State::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        for castling in ["HAha", "AHah", "KAkq", "HQha"] {
+            let fen = format!("r3k2r/8/8/8/8/8/8/R3K2R w {} - 0 1", castling);
+            let state = State::from_fen(&fen).unwrap();
+            assert_eq!(state.castling, 15, "{}", castling);
+            assert_eq!(state.to_fen(), standard.to_fen());
         }
+        assert_eq!(State::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w Hq - 0 1").unwrap().to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1");
+        assert!(State::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w B - 0 1").is_err());
     }
-    res
-}
\ No newline at end of file
+
+    #[test]
+    fn fen_syntax_errors() {
+        let cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0", FenError::FieldCount(5)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 x", FenError::FieldCount(7)),
+            ("rnbqkbnr/pppxpppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::InvalidChar {field: FenField::Placement, index: 12, c: 'x'}),
+            ("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::RankLength {rank: 7, index: 16}),
+            ("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::InvalidChar {field: FenField::Placement, index: 18, c: '9'}),
+            ("rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::RankCount(7)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1", FenError::InvalidField {field: FenField::SideToMove, index: 44}),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQxq - 0 1", FenError::InvalidChar {field: FenField::Castling, index: 48, c: 'x'}),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1", FenError::InvalidField {field: FenField::EnPassant, index: 51}),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - -1 1", FenError::InvalidField {field: FenField::HalfmoveClock, index: 53}),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 151 1", FenError::InvalidField {field: FenField::HalfmoveClock, index: 53}),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0", FenError::InvalidField {field: FenField::FullmoveNumber, index: 55}),
+        ];
+        for (fen, expected) in cases {
+            assert_eq!(State::from_fen(fen).err(), Some(expected), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn fen_illegal_positions() {
+        let cases = [
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::KingCount {color: PieceColor::Black, count: 0}),
+            ("k7/8/8/8/8/8/8/3KK3 w - - 0 1", FenError::KingCount {color: PieceColor::White, count: 2}),
+            ("k6P/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank {square: 7}),
+            ("k7/8/8/8/8/8/8/p3K3 w - - 0 1", FenError::PawnOnBackRank {square: 56}),
+            ("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1", FenError::CastlingWithoutRook {right: 'Q'}),
+            ("4k3/8/8/8/8/8/8/R2K3R w K - 0 1", FenError::CastlingWithoutRook {right: 'K'}),
+            ("r3k2r/8/8/8/8/8/8/4K3 w kqK - 0 1", FenError::CastlingWithoutRook {right: 'K'}),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d3 0 2", FenError::EnPassantImpossible {index: 27}),
+            ("4k3/8/8/4P3/8/8/8/4K3 w - d6 0 2", FenError::EnPassantImpossible {index: 26}),
+            ("4k3/8/3p4/3pP3/8/8/8/4K3 w - d6 0 2", FenError::EnPassantImpossible {index: 29}),
+            ("4k2R/8/8/8/8/8/8/4K3 w - - 0 1", FenError::OpponentInCheck),
+        ];
+        for (fen, expected) in cases {
+            assert_eq!(State::from_fen(fen).err(), Some(expected), "{}", fen);
+        }
+        assert!(State::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").is_ok());
+        assert!(State::from_fen("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").is_ok());
+        // being in check is fine for the side to move
+        assert!(State::from_fen("4k3/8/8/8/8/8/8/4K2r w - - 0 1").is_ok());
+    }
+
+    #[test]
+    fn fen_error_messages() {
+        let err = State::from_fen("rnbqkbnr/pppxpppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").err().unwrap();
+        assert_eq!(err.to_string(), "unexpected 'x' in piece placement at character 13");
+        let err = State::from_fen("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1").err().unwrap();
+        assert_eq!(err.to_string(), "castling right Q without king and rook on their squares");
+    }
+}
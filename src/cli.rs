@@ -0,0 +1,311 @@
+use std::io::{self, BufRead, Write};
+
+use ::chess::chess::{self, game_status, make_move_on, piece_to_char, unmake_move, GameStatus, Move, PieceColor, State, Undo, START_FEN};
+use ::chess::san;
+use ::chess::score::evaluate;
+use ::chess::search::{mate_in, Limits, SearchInfo, Searcher};
+use ::chess::tt;
+use crate::uci;
+
+const DEFAULT_DEPTH: usize = 6;
+
+const USAGE: &str = "\
+usage: chess [command] [options]
+
+commands:
+  uci               speak UCI on stdin/stdout (the default without a command)
+  analyse           print every search iteration, then the best move
+  bestmove          print the best move in coordinate notation
+  eval              print the static evaluation in centipawns
+  perft <depth>     count the leaf nodes below every legal move
+  play              play against the engine, moves in SAN or coordinates
+
+options:
+  --fen <fen>       position to start from instead of the initial one;
+                    '-' reads one FEN per line from stdin
+  --depth <n>       search depth, 6 when no limit is given
+  --movetime <ms>   search time in milliseconds
+  --nodes <n>       search at most this many nodes
+  --color <side>    side you play in `play`, white or black
+
+Scores are from the point of view of the side to move, #N is mate in N moves.";
+
+struct Options {
+    fen: Option<String>,
+    limits: Limits,
+    color: PieceColor,
+    args: Vec<String>, // anything that isn't an option
+}
+
+// `args` excludes the program name. Without a command the UCI loop is started.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let Some(command) = args.first() else {
+        uci::run();
+        return Ok(());
+    };
+    let opts = parse_options(&args[1..])?;
+    match command.as_str() {
+        "uci" => uci::run(),
+        "analyse" | "analyze" => for_each_position(&opts, |state| analyse(state, opts.limits))?,
+        "bestmove" => for_each_position(&opts, |state| {
+            let res = Searcher::new(tt::DEFAULT_SIZE_MB).search(state, &[], opts.limits, |_| {});
+            println!("{}", if res.best_move == Move::NULL {"none".to_string()} else {res.best_move.to_uci()});
+        })?,
+        "eval" => for_each_position(&opts, |state| println!("{}", evaluate(state)))?,
+        "perft" => {
+            let depth = opts.args.first().ok_or("perft needs a depth")?;
+            let depth = depth.parse().map_err(|_| format!("invalid depth {}", depth))?;
+            // the FEN may also follow the depth, as in `chess perft 5 <fen>`
+            let opts = Options {fen: opts.fen.clone().or(join_fen(&opts.args[1..])), ..opts};
+            for_each_position(&opts, |state| uci::print_divide(state, depth))?;
+        }
+        "play" => play(&opts)?,
+        "help" | "-h" | "--help" => println!("{}", USAGE),
+        _ => return Err(format!("unknown command {}\n\n{}", command, USAGE)),
+    }
+    Ok(())
+}
+
+fn join_fen(tokens: &[String]) -> Option<String> {
+    if tokens.is_empty() {None} else {Some(tokens.join(" "))}
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        fen: None,
+        limits: Limits::default(),
+        color: PieceColor::White,
+        args: Vec::new(),
+    };
+    let mut i = 0;
+    while i < args.len() {
+        let name = args[i].as_str();
+        i += 1;
+        if !name.starts_with("--") {
+            opts.args.push(name.to_string());
+            continue;
+        }
+        let value = args.get(i).ok_or(format!("{} needs a value", name))?;
+        let number = || value.parse::<u64>().map_err(|_| format!("invalid {} value {}", name, value));
+        match name {
+            // an unquoted FEN runs up to the next option
+            "--fen" => {
+                let end = args[i..].iter().position(|a| a.starts_with("--")).map_or(args.len(), |n| i + n);
+                opts.fen = Some(args[i..end].join(" "));
+                i = end;
+                continue;
+            }
+            "--depth" => opts.limits.depth = Some(number()? as usize),
+            "--movetime" => opts.limits.movetime = Some(number()?),
+            "--nodes" => opts.limits.nodes = Some(number()?),
+            "--color" => opts.color = match value.as_str() {
+                "white" | "w" => PieceColor::White,
+                "black" | "b" => PieceColor::Black,
+                _ => return Err(format!("invalid color {}", value)),
+            },
+            _ => return Err(format!("unknown option {}", name)),
+        }
+        i += 1;
+    }
+    if opts.limits.depth.is_none() && opts.limits.movetime.is_none() && opts.limits.nodes.is_none() {
+        opts.limits.depth = Some(DEFAULT_DEPTH);
+    }
+    Ok(opts)
+}
+
+fn parse_fen(fen: &str) -> Result<State, String> {
+    State::from_fen(fen).map_err(|err| format!("invalid fen '{}': {}", fen, err))
+}
+
+// Runs `f` on the --fen position, or on every FEN read from stdin with `--fen -`
+fn for_each_position(opts: &Options, mut f: impl FnMut(&State)) -> Result<(), String> {
+    match opts.fen.as_deref() {
+        Some("-") => {
+            for line in io::stdin().lock().lines() {
+                let line = line.map_err(|err| err.to_string())?;
+                if line.trim().is_empty() {continue;}
+                f(&parse_fen(line.trim())?);
+            }
+        }
+        fen => f(&parse_fen(fen.unwrap_or(START_FEN))?),
+    }
+    Ok(())
+}
+
+// "+0.35" in pawns, or "#3" / "#-2" for mates
+fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(n) => format!("#{}", n),
+        None => format!("{:+.2}", score as f64 / 100.0),
+    }
+}
+
+fn format_iteration(state: &State, info: &SearchInfo) -> String {
+    format!(
+        "depth {} score {} nodes {} time {} pv {}",
+        info.depth, format_score(info.score), info.nodes, info.time, san::to_san_line(state, &info.pv).join(" ")
+    )
+}
+
+fn analyse(state: &State, limits: Limits) {
+    let mut searcher = Searcher::new(tt::DEFAULT_SIZE_MB);
+    let res = searcher.search(state, &[], limits, |info| println!("{}", format_iteration(state, info)));
+    if res.best_move == Move::NULL {
+        println!("bestmove none");
+    } else {
+        println!("bestmove {}", san::to_san(state, res.best_move));
+    }
+}
+
+fn board_string(state: &State, flip: bool) -> String {
+    let mut rows = Vec::new();
+    rows.push("  +-----------------+".to_string());
+    for r in 0..8 {
+        let row = if flip {7 - r} else {r};
+        let mut line = format!("{} |", 8 - row);
+        for c in 0..8 {
+            let file = if flip {7 - c} else {c};
+            line.push(' ');
+            line.push(state.board[row * 8 + file].map_or('.', piece_to_char));
+        }
+        line.push_str(" |");
+        rows.push(line);
+    }
+    rows.push("  +-----------------+".to_string());
+    rows.push(if flip {"    h g f e d c b a"} else {"    a b c d e f g h"}.to_string());
+    rows.join("\n")
+}
+
+fn game_over_message(status: GameStatus, turn: PieceColor) -> String {
+    let winner = if turn == PieceColor::White {"black"} else {"white"};
+    match status {
+        GameStatus::Checkmate => format!("checkmate, {} wins", winner),
+        GameStatus::Stalemate => "draw by stalemate".to_string(),
+        GameStatus::ThreefoldRepetition => "draw by threefold repetition".to_string(),
+        GameStatus::FiftyMoveRule => "draw by the fifty-move rule".to_string(),
+        GameStatus::SeventyFiveMoveRule => "draw by the seventy-five-move rule".to_string(),
+        GameStatus::InsufficientMaterial => "draw by insufficient material".to_string(),
+        GameStatus::Ongoing => String::new(),
+    }
+}
+
+// Searches and announces the engine's move in `play`. If the engine finds no
+// move, the game ends with an error.
+fn engine_reply(searcher: &mut Searcher, state: &State, history: &[u64], limits: Limits) -> Result<Move, String> {
+    let res = searcher.search(state, history, limits, |_| {});
+    if res.best_move == Move::NULL {
+        return Err("the engine found no move to play".to_string());
+    }
+    println!("engine plays {} ({})", san::to_san(state, res.best_move), format_score(res.score));
+    Ok(res.best_move)
+}
+
+// Interactive game in the terminal. Besides moves, "undo" takes back your
+// last move and the engine's reply, "quit" ends the game.
+fn play(opts: &Options) -> Result<(), String> {
+    if opts.fen.as_deref() == Some("-") {
+        return Err("play reads moves from stdin, give the position with --fen <fen>".to_string());
+    }
+    let mut state = parse_fen(opts.fen.as_deref().unwrap_or(START_FEN))?;
+    let mut history = Vec::new();
+    let mut played: Vec<(Move, Undo)> = Vec::new();
+    let mut searcher = Searcher::new(tt::DEFAULT_SIZE_MB);
+    let mut input = io::stdin().lock().lines();
+    let flip = opts.color == PieceColor::Black;
+
+    loop {
+        println!("{}\n", board_string(&state, flip));
+        let status = game_status(&state, &history);
+        if status != GameStatus::Ongoing {
+            println!("{}", game_over_message(status, state.turn));
+            return Ok(());
+        }
+
+        let mv = if state.turn == opts.color {
+            print!("your move: ");
+            io::stdout().flush().map_err(|err| err.to_string())?;
+            let Some(line) = input.next() else {return Ok(())};
+            let line = line.map_err(|err| err.to_string())?;
+            match line.trim() {
+                "" => continue,
+                "quit" => return Ok(()),
+                "undo" => {
+                    while let Some((mv, undo)) = played.pop() {
+                        unmake_move(mv, &undo, &mut state);
+                        history.pop();
+                        if state.turn == opts.color {break;}
+                    }
+                    continue;
+                }
+                text => match chess::parse_uci_move(&state, text).or_else(|| san::parse_san(&state, text)) {
+                    Some(mv) => mv,
+                    None => {
+                        println!("illegal move {}", text);
+                        continue;
+                    }
+                },
+            }
+        } else {
+            engine_reply(&mut searcher, &state, &history, opts.limits)?
+        };
+        history.push(state.zob_hash);
+        played.push((mv, make_move_on(mv, &mut state)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::chess::search::MATE;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn options() {
+        let opts = parse_options(&args("--fen 8/8/4k3/8/8/3K4/8/R7 w - - 0 1 --movetime 50 --color black")).unwrap();
+        assert_eq!(opts.fen.as_deref(), Some("8/8/4k3/8/8/3K4/8/R7 w - - 0 1"));
+        assert_eq!(opts.limits.movetime, Some(50));
+        assert_eq!(opts.limits.depth, None);
+        assert_eq!(opts.color, PieceColor::Black);
+
+        let opts = parse_options(&args("5 --fen -")).unwrap();
+        assert_eq!(opts.args, ["5"]);
+        assert_eq!(opts.fen.as_deref(), Some("-"));
+        assert_eq!(opts.limits.depth, Some(DEFAULT_DEPTH));
+
+        assert!(parse_options(&args("--depth")).is_err());
+        assert!(parse_options(&args("--depth deep")).is_err());
+        assert!(parse_options(&args("--colour white")).is_err());
+        assert!(run(&args("perft")).is_err());
+        assert!(run(&args("eval --fen 8/8/8/8/8/8/8/8 w - - 0 1")).is_err());
+    }
+
+    #[test]
+    fn output_formats() {
+        assert_eq!(format_score(35), "+0.35");
+        assert_eq!(format_score(-120), "-1.20");
+        assert_eq!(format_score(MATE - 3), "#2");
+        assert_eq!(format_score(-MATE + 2), "#-1");
+
+        let state = State::from_fen(START_FEN).unwrap();
+        let board = board_string(&state, false);
+        assert!(board.contains("8 | r n b q k b n r |"));
+        assert!(board.ends_with("a b c d e f g h"));
+        let flipped = board_string(&state, true);
+        assert!(flipped.contains("1 | R N B K Q B N R |"));
+    }
+
+    #[test]
+    fn engine_reply_is_a_legal_move() {
+        let mut searcher = Searcher::new(1);
+        let limits = Limits {nodes: Some(1), ..Limits::default()};
+        let state = State::from_fen(START_FEN).unwrap();
+        let mv = engine_reply(&mut searcher, &state, &[], limits).unwrap();
+        assert!(chess::get_all_valid_moves(&state).contains(&mv));
+
+        let mated = State::from_fen("7k/7Q/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(engine_reply(&mut searcher, &mated, &[], limits).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::position::Position;
+use crate::score::Evaluator;
+use crate::search::{Limits, SearchInfo, SearchResult, Searcher};
+use crate::tt::{self, TranspositionTable};
+
+/// A search engine with its own transposition table and evaluation. Engines
+/// share no state, so each thread or game can own one.
+///
+/// ```
+/// use chess::{Engine, Limits, Position};
+///
+/// let mut engine = Engine::new();
+/// let position = Position::from_fen("7k/8/6K1/8/8/8/8/5Q2 w - - 0 1").unwrap();
+/// let result = engine.search(&position, Limits {depth: Some(3), ..Limits::default()});
+/// assert_eq!(result.best_move.to_uci(), "f1f8");
+/// assert_eq!(chess::search::mate_in(result.score), Some(1));
+/// ```
+pub struct Engine {
+    searcher: Searcher,
+    hash_mb: usize,
+}
+
+impl Engine {
+    /// An engine with a transposition table of the default size.
+    pub fn new() -> Self {
+        Engine::with_hash_size(tt::DEFAULT_SIZE_MB)
+    }
+
+    /// A transposition table of `size_mb` megabytes, clamped to
+    /// `1..=tt::MAX_SIZE_MB`.
+    pub fn with_hash_size(size_mb: usize) -> Self {
+        Engine {searcher: Searcher::new(size_mb), hash_mb: size_mb}
+    }
+
+    /// Replaces the static evaluation, e.g. with a closure
+    /// `|state: &State| -> i32` scoring for the side to move.
+    pub fn set_evaluator(&mut self, evaluator: impl Evaluator + 'static) {
+        self.searcher.evaluator = Box::new(evaluator);
+    }
+
+    /// Empties the transposition table, e.g. before an unrelated game.
+    pub fn clear_hash(&mut self) {
+        self.searcher.tt = TranspositionTable::new(self.hash_mb);
+    }
+
+    /// Setting the flag from another thread makes a running search return
+    /// the best move found so far. It is cleared when the search returns.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.searcher.stop.clone()
+    }
+
+    /// Searches `position` until a limit runs out. Without limits the search
+    /// only ends through the stop flag or at the maximum depth.
+    /// `best_move` is `Move::NULL` when there is no legal move.
+    pub fn search(&mut self, position: &Position, limits: Limits) -> SearchResult {
+        self.search_with_info(position, limits, |_| {})
+    }
+
+    /// Like `search`, calling `report` after every completed depth.
+    pub fn search_with_info(&mut self, position: &Position, limits: Limits, report: impl FnMut(&SearchInfo)) -> SearchResult {
+        let res = self.searcher.search(position.state(), position.history(), limits, report);
+        self.searcher.stop.store(false, Ordering::Relaxed);
+        res
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::State;
+    use std::thread;
+
+    #[test]
+    fn evaluation_hook() {
+        // an evaluation that only likes having the white king on h1
+        let mut engine = Engine::new();
+        engine.set_evaluator(|state: &State| {
+            let score = if state.board[63].is_some() {100} else {0};
+            if state.turn == crate::chess::PieceColor::White {score} else {-score}
+        });
+        let position = Position::from_fen("4k3/p7/8/8/8/8/P7/6K1 w - - 0 1").unwrap();
+        let res = engine.search(&position, Limits {depth: Some(1), ..Limits::default()});
+        assert_eq!(res.best_move.to_uci(), "g1h1");
+        assert_eq!(res.score, 100);
+    }
+
+    #[test]
+    fn independent_engines_on_threads() {
+        let fens = ["7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", "7k/8/6K1/8/8/8/8/5Q2 w - - 0 1"];
+        let handles = fens.map(|fen| thread::spawn(move || {
+            let mut engine = Engine::with_hash_size(1);
+            engine.search(&Position::from_fen(fen).unwrap(), Limits {depth: Some(4), ..Limits::default()}).best_move.to_uci()
+        }));
+        let moves = handles.map(|handle| handle.join().unwrap());
+        assert_eq!(moves, ["a2a7", "f1f8"]);
+    }
+}
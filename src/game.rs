@@ -0,0 +1,171 @@
+use crate::chess::{game_status, get_all_valid_moves, make_move_on, unmake_move, GameStatus, Move, State, Undo};
+
+// A game from a starting position. Moves after the current ply stay
+// recorded after an undo so they can be redone, until a different move is played.
+#[derive(Clone)]
+pub struct Game {
+    start: State,
+    state: State,
+    moves: Vec<Move>,
+    undos: Vec<Undo>, // one per ply played, undos[i] takes back moves[i]
+}
+
+impl Game {
+    pub fn new(start: State) -> Self {
+        Game {
+            state: start.clone(),
+            start,
+            moves: Vec::new(),
+            undos: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    // Number of moves currently played
+    pub fn ply(&self) -> usize {
+        self.undos.len()
+    }
+
+    // Every recorded move, including the ones that were undone
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    // Hashes of the positions before the current one, oldest first
+    pub fn history(&self) -> Vec<u64> {
+        self.undos.iter().map(|undo| undo.zob_hash).collect()
+    }
+
+    pub fn status(&self) -> GameStatus {
+        game_status(&self.state, &self.history())
+    }
+
+    // Plays `mv` if it is legal, dropping any undone moves
+    pub fn make_move(&mut self, mv: Move) -> bool {
+        if !get_all_valid_moves(&self.state).contains(&mv) {
+            return false;
+        }
+        self.moves.truncate(self.ply());
+        self.moves.push(mv);
+        self.undos.push(make_move_on(mv, &mut self.state));
+        true
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.undos.pop() {
+            Some(undo) => {
+                unmake_move(self.moves[self.undos.len()], &undo, &mut self.state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.moves.get(self.ply()) {
+            Some(&mv) => {
+                self.undos.push(make_move_on(mv, &mut self.state));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Undoes or redoes moves until `ply` moves are played
+    pub fn goto_ply(&mut self, ply: usize) -> bool {
+        if ply > self.moves.len() {
+            return false;
+        }
+        while self.ply() > ply {
+            self.undo();
+        }
+        while self.ply() < ply {
+            self.redo();
+        }
+        true
+    }
+
+    // The position after the first `ply` recorded moves
+    pub fn position(&self, ply: usize) -> Option<State> {
+        if ply > self.moves.len() {
+            return None;
+        }
+        let mut state = self.start.clone();
+        for &mv in &self.moves[..ply] {
+            make_move_on(mv, &mut state);
+        }
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{DOUBLE_PUSH, QUIET, START_FEN};
+
+    fn start() -> Game {
+        let state = State::from_fen(START_FEN).unwrap();
+        Game::new(state)
+    }
+
+    fn e4() -> Move {Move::new(52, 36, DOUBLE_PUSH)}
+    fn e5() -> Move {Move::new(12, 28, DOUBLE_PUSH)}
+    fn nf3() -> Move {Move::new(62, 45, QUIET)}
+    fn nc3() -> Move {Move::new(57, 42, QUIET)}
+
+    fn opening() -> Game {
+        let mut game = start();
+        assert!(game.make_move(e4()) && game.make_move(e5()) && game.make_move(nf3()));
+        game
+    }
+
+    #[test]
+    fn rejects_illegal_moves() {
+        let mut game = start();
+        assert!(!game.make_move(e5()));
+        assert!(!game.make_move(Move::new(52, 28, DOUBLE_PUSH)));
+        assert_eq!(game.ply(), 0);
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut game = opening();
+        let end = game.state().zob_hash;
+
+        assert!(game.undo() && game.undo() && game.undo());
+        assert!(!game.undo());
+        assert_eq!(game.state().zob_hash, start().state().zob_hash);
+        assert_eq!(game.moves().len(), 3);
+
+        assert!(game.redo() && game.redo() && game.redo());
+        assert!(!game.redo());
+        assert_eq!(game.state().zob_hash, end);
+        assert_eq!(game.history().len(), 3);
+        assert_eq!(game.history()[0], start().state().zob_hash);
+    }
+
+    #[test]
+    fn new_move_drops_undone_moves() {
+        let mut game = opening();
+        game.goto_ply(2);
+        assert!(game.make_move(nc3()));
+        assert_eq!(game.moves(), &[e4(), e5(), nc3()]);
+        assert!(!game.redo());
+    }
+
+    #[test]
+    fn goto_ply_matches_positions() {
+        let mut game = opening();
+        for ply in [1, 3, 0, 2] {
+            assert!(game.goto_ply(ply));
+            assert_eq!(game.state().zob_hash, game.position(ply).unwrap().zob_hash);
+            assert!(game.state().board == game.position(ply).unwrap().board);
+        }
+        assert!(!game.goto_ply(4));
+        assert!(game.position(4).is_none());
+        assert!(game.position(0).unwrap().board == start().state().board);
+    }
+}
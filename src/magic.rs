@@ -0,0 +1,260 @@
+use std::sync::LazyLock;
+
+use crate::bitboard::{bishop_rays, relevant_occupancy, rook_rays};
+
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    offset: usize, // start of this square's slice of the attack table
+}
+
+pub struct SliderAttacks {
+    rook: Vec<Magic>,
+    bishop: Vec<Magic>,
+    table: Vec<u64>,
+}
+
+pub static SLIDER_ATTACKS: LazyLock<SliderAttacks> = LazyLock::new(SliderAttacks::init);
+
+impl SliderAttacks {
+    fn init() -> Self {
+        let mut table = Vec::new();
+        let rook = (0..64).map(|sq| fill(sq, true, ROOK_MAGICS[sq], &mut table)).collect();
+        let bishop = (0..64).map(|sq| fill(sq, false, BISHOP_MAGICS[sq], &mut table)).collect();
+        SliderAttacks { rook, bishop, table }
+    }
+
+    pub fn rook(&self, sq: usize, occupied: u64) -> u64 {
+        self.lookup(&self.rook[sq], occupied)
+    }
+
+    pub fn bishop(&self, sq: usize, occupied: u64) -> u64 {
+        self.lookup(&self.bishop[sq], occupied)
+    }
+
+    fn lookup(&self, m: &Magic, occupied: u64) -> u64 {
+        self.table[m.offset + ((occupied & m.mask).wrapping_mul(m.magic) >> m.shift) as usize]
+    }
+}
+
+// Appends the attacks for every blocker subset of the square's mask to the table
+fn fill(sq: usize, rook: bool, magic: u64, table: &mut Vec<u64>) -> Magic {
+    let mask = relevant_occupancy(sq, rook);
+    let bits = mask.count_ones();
+    let m = Magic {
+        mask,
+        magic,
+        shift: 64 - bits,
+        offset: table.len(),
+    };
+    table.resize(table.len() + (1 << bits), 0);
+
+    // enumerate all subsets of the mask (carry-rippler)
+    let mut occupied = 0u64;
+    loop {
+        let attacks = if rook {rook_rays(sq, occupied)} else {bishop_rays(sq, occupied)};
+        table[m.offset + (occupied.wrapping_mul(magic) >> m.shift) as usize] = attacks;
+        occupied = occupied.wrapping_sub(mask) & mask;
+        if occupied == 0 {break;}
+    }
+    m
+}
+
+
+// Found by trial and error, see `generate_magics` in the tests
+pub const ROOK_MAGICS: [u64; 64] = [
+    0x2080002080400010,
+    0x00C0002001401000,
+    0x2100110008402002,
+    0x0880080081041000,
+    0x0200020020041008,
+    0x2300040008010012,
+    0x0C00283004008201,
+    0x0180010000407A80,
+    0x0168800080400020,
+    0x0010400040201000,
+    0x1001002001001048,
+    0x1001002408100100,
+    0x0801000408010012,
+    0x4001000209000400,
+    0x08A20004C8020001,
+    0x2002801145002280,
+    0x0080860021004200,
+    0x001000C009402002,
+    0x00B0002004002800,
+    0x100A808010020800,
+    0x8101010008000410,
+    0x0244008002000480,
+    0x0000040010810208,
+    0x2000020000448534,
+    0x4104400480008033,
+    0x0000810100204000,
+    0x0440430900200010,
+    0x4600240900100100,
+    0x0060080080040080,
+    0x0001000300080400,
+    0x0004084400011002,
+    0x0023040200008041,
+    0x0580050043002080,
+    0x0400804002802008,
+    0x0001002001004010,
+    0x1000200901001000,
+    0x4410800801800C00,
+    0xA012003806001004,
+    0x0020100104008802,
+    0x0004808402000041,
+    0x0010400170898000,
+    0x0080500020004004,
+    0x1040408012020020,
+    0x8010040008004040,
+    0x2001080100110004,
+    0x0000020004008080,
+    0x0021010810040002,
+    0x0800008C43020024,
+    0x0000800021005100,
+    0x0070201040008080,
+    0x0000D04282006A00,
+    0x0010014400080240,
+    0x0001080110050100,
+    0x0012000810240600,
+    0x0402000801040200,
+    0x028100108A004100,
+    0x0050800300102045,
+    0x8208210040120882,
+    0x8010600101183441,
+    0x020B000910006045,
+    0x0241001002480005,
+    0x0081000400880241,
+    0x0000009008024124,
+    0x0048122980410402,
+];
+
+pub const BISHOP_MAGICS: [u64; 64] = [
+    0x0848020822040013,
+    0x8010A40085821200,
+    0x0008008430840822,
+    0x0808048108040000,
+    0x1304042100008104,
+    0x5001012010204023,
+    0x81048801B8200420,
+    0x200A008084012000,
+    0x0040102001042084,
+    0x840A505042428020,
+    0x0000700102202920,
+    0x44101C0C10800002,
+    0x0040040422000000,
+    0x0180020802090202,
+    0x4020020811041202,
+    0x000104308C042000,
+    0x4140661002424400,
+    0x0028012008010460,
+    0x0188062102002A00,
+    0x0014004840102008,
+    0x0105000290400002,
+    0x8001022200410400,
+    0x104A041918013446,
+    0x008A000082008238,
+    0x04A0060008100430,
+    0x0008220008820801,
+    0x2508041208005010,
+    0x4008080200202020,
+    0x2441001013004000,
+    0x0030008060407000,
+    0x4008108000420800,
+    0x0012021050290100,
+    0x0210080482200500,
+    0xCC01112048100480,
+    0x0020402806500440,
+    0x00048E0080580080,
+    0x0040102020020080,
+    0x0028010440080807,
+    0x4601041108008800,
+    0x8040810E04104200,
+    0x901210110400088A,
+    0xA003080212081050,
+    0x00C1004048401004,
+    0x900000A014400800,
+    0x0008021040405401,
+    0x4020008206002090,
+    0x0004190424030100,
+    0x0424008A02026250,
+    0x8004088250900040,
+    0x1C00430088A04200,
+    0x0001020094040001,
+    0x8040210020880061,
+    0x2010040450442032,
+    0x0800840850044001,
+    0x0004040802140004,
+    0x0004080A04222020,
+    0x8088802110022000,
+    0x1081A10416114400,
+    0x0205010A24060820,
+    0x0000000720411080,
+    0x1008000208430400,
+    0x580C026028810840,
+    0x802020441020A110,
+    0x12C0022401020018,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xorshift(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    #[test]
+    fn lookups_match_ray_walking() {
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        for sq in 0..64 {
+            for _ in 0..200 {
+                // sparse boards are the common case, dense ones stress blockers
+                let occupied = (xorshift(&mut seed) & xorshift(&mut seed)) | if sq % 2 == 0 {xorshift(&mut seed)} else {0};
+                assert_eq!(SLIDER_ATTACKS.rook(sq, occupied), rook_rays(sq, occupied), "rook {} {:x}", sq, occupied);
+                assert_eq!(SLIDER_ATTACKS.bishop(sq, occupied), bishop_rays(sq, occupied), "bishop {} {:x}", sq, occupied);
+            }
+        }
+    }
+
+    // A magic is valid if no two blocker subsets with different attacks land
+    // on the same index
+    fn find_magic(sq: usize, rook: bool, seed: &mut u64) -> u64 {
+        let mask = relevant_occupancy(sq, rook);
+        let bits = mask.count_ones();
+        'search: loop {
+            let magic = xorshift(seed) & xorshift(seed) & xorshift(seed);
+            let mut used = vec![None; 1 << bits];
+            let mut occupied = 0u64;
+            loop {
+                let attacks = if rook {rook_rays(sq, occupied)} else {bishop_rays(sq, occupied)};
+                let index = (occupied.wrapping_mul(magic) >> (64 - bits)) as usize;
+                match used[index] {
+                    Some(a) if a != attacks => continue 'search,
+                    _ => used[index] = Some(attacks),
+                }
+                occupied = occupied.wrapping_sub(mask) & mask;
+                if occupied == 0 {return magic;}
+            }
+        }
+    }
+
+    // Prints a fresh set of magics: cargo test generate_magics -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn generate_magics() {
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        for (name, rook) in [("ROOK_MAGICS", true), ("BISHOP_MAGICS", false)] {
+            println!("pub const {}: [u64; 64] = [", name);
+            for sq in 0..64 {
+                println!("    0x{:016X},", find_magic(sq, rook, &mut seed));
+            }
+            println!("];");
+        }
+    }
+}
+
@@ -1,20 +1,11 @@
+mod cli;
+mod uci;
 
-use crate::chess::{State, state_from_fen};
-
-mod search;
-mod chess;
-mod score;
-mod zobrist;
-
-// use crate::zobrist::generate_keys;
-
-
+// See cli::run for the commands, without any the UCI loop is started
 fn main() {
-    let mut state = State::new();
-    let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string();
-    state_from_fen(fen, &mut state).unwrap();
-    let res = search::find_move(&state, 3);
-    println!("{}, {}, {}", res.0 .0, res.0 .1, res.1);
-
-    // println!("{:#?}", generate_keys());
-}
\ No newline at end of file
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if let Err(err) = cli::run(&args) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
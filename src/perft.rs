@@ -0,0 +1,105 @@
+use crate::chess::{get_all_valid_moves, make_move_on, unmake_move, Move, State};
+
+// Number of leaf nodes of the legal move tree, the standard move generator check
+pub fn perft(state: &State, depth: usize) -> u64 {
+    perft_inner(&mut state.clone(), depth)
+}
+
+// Node counts below every root move, for tracking down which move is wrong
+pub fn perft_divide(state: &State, depth: usize) -> Vec<(Move, u64)> {
+    get_all_valid_moves(state).into_iter().map(|mv| {
+        let mut child = state.clone();
+        make_move_on(mv, &mut child);
+        (mv, perft(&child, depth.saturating_sub(1)))
+    }).collect()
+}
+
+fn perft_inner(state: &mut State, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = get_all_valid_moves(state);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves {
+        let undo = make_move_on(mv, state);
+        nodes += perft_inner(state, depth - 1);
+        unmake_move(mv, &undo, state);
+    }
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // https://www.chessprogramming.org/Perft_Results
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_4_MIRRORED: &str = "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    fn check(fen: &str, expected: &[u64]) {
+        let state = State::from_fen(fen).unwrap();
+        for (i, &nodes) in expected.iter().enumerate() {
+            assert_eq!(perft(&state, i + 1), nodes, "{} at depth {}", fen, i + 1);
+        }
+    }
+
+    #[test]
+    fn start_position() {
+        check(START, &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        check(KIWIPETE, &[48, 2039, 97862]);
+    }
+
+    #[test]
+    fn position_3() {
+        check(POSITION_3, &[14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn position_4() {
+        check(POSITION_4, &[6, 264, 9467]);
+        check(POSITION_4_MIRRORED, &[6, 264, 9467]);
+    }
+
+    #[test]
+    fn position_5() {
+        check(POSITION_5, &[44, 1486, 62379]);
+    }
+
+    #[test]
+    fn position_6() {
+        check(POSITION_6, &[46, 2079, 89890]);
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let state = State::from_fen(KIWIPETE).unwrap();
+        let divide = perft_divide(&state, 2);
+        assert_eq!(divide.len(), 48);
+        assert_eq!(divide.iter().map(|&(_, nodes)| nodes).sum::<u64>(), 2039);
+    }
+
+    // cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn deep() {
+        check(START, &[20, 400, 8902, 197281, 4865609]);
+        check(KIWIPETE, &[48, 2039, 97862, 4085603]);
+        check(POSITION_3, &[14, 191, 2812, 43238, 674624, 11030083]);
+        check(POSITION_4, &[6, 264, 9467, 422333]);
+        check(POSITION_5, &[44, 1486, 62379, 2103487]);
+        check(POSITION_6, &[46, 2079, 89890, 3894594]);
+    }
+}
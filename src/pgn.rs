@@ -0,0 +1,510 @@
+use std::fmt;
+
+use crate::chess::{make_move_on, GameStatus, Move, PieceColor, State, START_FEN};
+use crate::san::{parse_san, to_san};
+
+// Tags every PGN game carries, in the order they have to be written
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+const MAX_LINE: usize = 79;
+
+// One game of a PGN file. Comments and NAGs are kept with the number of
+// moves played before them, so (0, ..) comes before the first move.
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: State,
+    pub moves: Vec<Move>,
+    pub comments: Vec<(usize, String)>,
+    pub nags: Vec<(usize, u8)>,
+    pub result: String, // "1-0", "0-1", "1/2-1/2" or "*"
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PgnError {
+    pub line: usize, // both start at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Result tag for a game that ended with `status`, `turn` being the side to move
+pub fn result_for(status: GameStatus, turn: PieceColor) -> &'static str {
+    match status {
+        GameStatus::Ongoing => "*",
+        GameStatus::Checkmate if turn == PieceColor::White => "0-1",
+        GameStatus::Checkmate => "1-0",
+        _ => "1/2-1/2",
+    }
+}
+
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Result(String),
+    San(String),
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> PgnError {
+        PgnError {line: self.line, column: self.column, message: message.to_string()}
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {break;}
+        }
+    }
+
+    fn symbol(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_alphanumeric() && !"_+#=:-/.!?".contains(c) {break;}
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+
+    fn quoted(&mut self) -> Result<String, PgnError> {
+        if self.bump() != Some('"') {
+            return Err(self.error("expected a quoted tag value"));
+        }
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some(c) => s.push(c),
+                    None => return Err(self.error("unterminated tag value")),
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated tag value")),
+            }
+            if self.peek() == Some('\n') {
+                return Err(self.error("unterminated tag value"));
+            }
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    // Next token with the line and column it starts at
+    fn next(&mut self) -> Result<Option<(Token, usize, usize)>, PgnError> {
+        loop {
+            self.skip_spaces();
+            // escape mechanism: lines starting with % are ignored
+            if self.column == 1 && self.peek() == Some('%') {
+                self.skip_line();
+                continue;
+            }
+            let (line, column) = (self.line, self.column);
+            let Some(c) = self.peek() else {return Ok(None)};
+
+            let token = match c {
+                ';' => {
+                    self.bump();
+                    let mut s = String::new();
+                    while let Some(c) = self.bump() {
+                        if c == '\n' {break;}
+                        s.push(c);
+                    }
+                    Token::Comment(s.trim().to_string())
+                }
+                '{' => {
+                    self.bump();
+                    let mut s = String::new();
+                    loop {
+                        match self.bump() {
+                            Some('}') => break,
+                            Some(c) => s.push(c),
+                            None => return Err(PgnError {line, column, message: "unterminated comment".to_string()}),
+                        }
+                    }
+                    Token::Comment(s.split_whitespace().collect::<Vec<&str>>().join(" "))
+                }
+                '[' => {
+                    self.bump();
+                    self.skip_spaces();
+                    let name = self.symbol();
+                    if name.is_empty() {
+                        return Err(self.error("expected a tag name"));
+                    }
+                    self.skip_spaces();
+                    let value = self.quoted()?;
+                    self.skip_spaces();
+                    if self.bump() != Some(']') {
+                        return Err(self.error("expected ] after tag"));
+                    }
+                    Token::Tag(name, value)
+                }
+                '(' => {
+                    self.bump();
+                    Token::Open
+                }
+                ')' => {
+                    self.bump();
+                    Token::Close
+                }
+                '*' => {
+                    self.bump();
+                    Token::Result("*".to_string())
+                }
+                '$' => {
+                    self.bump();
+                    match self.symbol().parse::<u8>() {
+                        Ok(nag) => Token::Nag(nag),
+                        Err(_) => return Err(PgnError {line, column, message: "invalid NAG".to_string()}),
+                    }
+                }
+                _ => {
+                    let symbol = self.symbol();
+                    if symbol.is_empty() {
+                        self.bump();
+                        return Err(PgnError {line, column, message: format!("unexpected character '{}'", c)});
+                    }
+                    // move numbers, possibly glued to the move as in "12.e4" or "12...Nf6"
+                    let san = symbol.trim_start_matches(|c: char| c.is_ascii_digit());
+                    let san = if san.starts_with('.') {san.trim_start_matches('.')} else {symbol.as_str()};
+                    match san {
+                        "" => continue,
+                        "1-0" | "0-1" | "1/2-1/2" => Token::Result(san.to_string()),
+                        _ => Token::San(san.to_string()),
+                    }
+                }
+            };
+            return Ok(Some((token, line, column)));
+        }
+    }
+}
+
+// "!", "?", "!!", "??", "!?" and "?!" are shorthands for NAGs 1 to 6
+fn suffix_nag(suffix: &str) -> Option<u8> {
+    match suffix {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    }
+}
+
+// A game being read, with the position after its moves so far
+struct Reader {
+    game: PgnGame,
+    state: State,
+}
+
+impl Reader {
+    fn new(tags: Vec<(String, String)>) -> Result<Self, String> {
+        let fen = tags.iter().find(|(name, _)| name == "FEN").map_or(START_FEN, |(_, value)| value);
+        let start = State::from_fen(fen).map_err(|err| format!("invalid FEN tag: {}", err))?;
+        let result = tags.iter().find(|(name, _)| name == "Result").map_or("*", |(_, value)| value).to_string();
+        Ok(Reader {
+            state: start.clone(),
+            game: PgnGame {tags, start, moves: Vec::new(), comments: Vec::new(), nags: Vec::new(), result},
+        })
+    }
+
+    // Only comments, e.g. trailing text after the last result
+    fn is_empty(&self) -> bool {
+        self.game.tags.is_empty() && self.game.moves.is_empty()
+    }
+}
+
+// Reads every game of a PGN file. Variations are checked for balanced
+// parentheses but their moves are skipped.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut lexer = Lexer {chars: text.chars().collect(), pos: 0, line: 1, column: 1};
+    let mut games = Vec::new();
+    let mut tags = Vec::new();
+    let mut reader: Option<Reader> = None;
+    let mut depth = 0;
+
+    while let Some((token, line, column)) = lexer.next()? {
+        let error = |message: String| PgnError {line, column, message};
+        if depth > 0 {
+            match token {
+                Token::Open => depth += 1,
+                Token::Close => depth -= 1,
+                Token::Tag(..) | Token::Result(_) => return Err(error("unterminated variation".to_string())),
+                _ => {}
+            }
+            continue;
+        }
+
+        if let Token::Tag(name, value) = token {
+            // a tag after movetext starts the next game even without a result
+            if let Some(done) = reader.take().filter(|done| !done.is_empty()) {
+                games.push(done.game);
+            }
+            tags.push((name, value));
+            continue;
+        }
+        if reader.is_none() {
+            reader = Some(Reader::new(std::mem::take(&mut tags)).map_err(error)?);
+        }
+        let current = reader.as_mut().unwrap();
+        let ply = current.game.moves.len();
+
+        match token {
+            Token::Comment(comment) => current.game.comments.push((ply, comment)),
+            Token::Nag(nag) => current.game.nags.push((ply, nag)),
+            Token::Open => {
+                if ply == 0 {
+                    return Err(error("variation before the first move".to_string()));
+                }
+                depth = 1;
+            }
+            Token::Close => return Err(error("unmatched )".to_string())),
+            Token::Result(result) => {
+                current.game.result = result;
+                games.push(reader.take().unwrap().game);
+            }
+            Token::San(san) => {
+                let bare = san.trim_end_matches(['!', '?']);
+                let nag = suffix_nag(&san[bare.len()..]);
+                if bare.is_empty() {
+                    // annotation written apart from its move
+                    current.game.nags.extend(nag.map(|nag| (ply, nag)));
+                    continue;
+                }
+                current.game.nags.extend(nag.map(|nag| (ply + 1, nag)));
+                match parse_san(&current.state, bare) {
+                    Some(mv) => {
+                        make_move_on(mv, &mut current.state);
+                        current.game.moves.push(mv);
+                    }
+                    None => return Err(error(format!("illegal or ambiguous move {}", san))),
+                }
+            }
+            Token::Tag(..) => unreachable!(),
+        }
+    }
+
+    if depth > 0 {
+        return Err(lexer.error("unterminated variation"));
+    }
+    match reader {
+        Some(current) if !current.is_empty() => games.push(current.game),
+        Some(_) => {}
+        None if !tags.is_empty() => games.push(Reader::new(tags).map_err(|message| lexer.error(&message))?.game),
+        None => {}
+    }
+    Ok(games)
+}
+
+impl PgnGame {
+    pub fn new(start: State, moves: Vec<Move>, result: &str) -> Self {
+        PgnGame {tags: Vec::new(), start, moves, comments: Vec::new(), nags: Vec::new(), result: result.to_string()}
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    // Export format: the Seven Tag Roster first, SetUp/FEN for other start
+    // positions, then the remaining tags and the movetext wrapped at 79 columns
+    pub fn to_pgn(&self) -> String {
+        let mut out = String::new();
+        let mut write_tag = |name: &str, value: &str| {
+            out.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        };
+        for (name, default) in SEVEN_TAG_ROSTER {
+            let value = if name == "Result" {Some(self.result.as_str())} else {self.tag(name)};
+            write_tag(name, value.unwrap_or(default));
+        }
+        let fen = self.start.to_fen();
+        if fen != START_FEN {
+            write_tag("SetUp", "1");
+            write_tag("FEN", &fen);
+        }
+        for (name, value) in self.tags.iter() {
+            if !SEVEN_TAG_ROSTER.iter().any(|&(n, _)| n == name) && name != "SetUp" && name != "FEN" {
+                write_tag(name, value);
+            }
+        }
+        out.push('\n');
+
+        let mut tokens = Vec::new();
+        let mut state = self.start.clone();
+        let black_first = (self.start.turn == PieceColor::Black) as usize;
+        let mut number_next = true; // black's moves need "N..." after a comment
+        for ply in 0..=self.moves.len() {
+            for &(_, nag) in self.nags.iter().filter(|&&(p, _)| p == ply) {
+                tokens.push(format!("${}", nag));
+            }
+            for (_, comment) in self.comments.iter().filter(|(p, _)| *p == ply) {
+                tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+                number_next = true;
+            }
+            let Some(&mv) = self.moves.get(ply) else {break};
+            let number = self.start.fullmove_number as usize + (ply + black_first) / 2;
+            if state.turn == PieceColor::White {
+                tokens.push(format!("{}.", number));
+            } else if number_next {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(to_san(&state, mv));
+            make_move_on(mv, &mut state);
+            number_next = false;
+        }
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE {
+                out.push_str(&line);
+                out.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        out.push_str(&line);
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERA_GAME: &str = r#"[Event "Paris"]
+[Site "Paris FRA"]
+[Date "1858.??.??"]
+[Round "?"]
+[White "Paul Morphy"]
+[Black "Duke Karl / Count Isouard"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 {This is a weak move already.} 4. dxe5 Bxf3 5. Qxf3
+dxe5 6. Bc4 Nf6 7. Qb3 Qe7 8. Nc3 c6 9. Bg5 {Black is in what's like a
+zugzwang position here.} b5 10. Nxb5 cxb5 11. Bxb5+ Nbd7 12. O-O-O Rd8 13. Rxd7
+Rxd7 14. Rd1 Qe6 15. Bxd7+ Nxd7 16. Qb8+ Nxb8 17. Rd8# 1-0
+"#;
+
+    #[test]
+    fn reads_a_game() {
+        let games = parse_pgn(OPERA_GAME).unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.moves.len(), 33);
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.tag("White"), Some("Paul Morphy"));
+        assert_eq!(game.comments[0], (6, "This is a weak move already.".to_string()));
+        assert_eq!(game.comments[1], (17, "Black is in what's like a zugzwang position here.".to_string()));
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let game = &parse_pgn(OPERA_GAME).unwrap()[0];
+        let pgn = game.to_pgn();
+        assert!(pgn.starts_with("[Event \"Paris\"]\n[Site \"Paris FRA\"]\n[Date \"1858.??.??\"]\n"));
+        assert!(pgn.contains("3. d4 Bg4 {This is a weak move already.} 4. dxe5"));
+        assert!(pgn.contains("{Black is in what's like a zugzwang position here.} 9... b5"));
+        assert!(pgn.trim_end().ends_with("17. Rd8# 1-0"));
+        assert!(pgn.lines().all(|line| line.len() <= MAX_LINE));
+
+        let again = &parse_pgn(&pgn).unwrap()[0];
+        assert_eq!(again.moves, game.moves);
+        assert_eq!(again.tags, game.tags);
+        assert_eq!(again.comments, game.comments);
+        assert_eq!(again.to_pgn(), pgn);
+    }
+
+    #[test]
+    fn skips_variations_and_reads_nags() {
+        let pgn = "1. e4 $1 e5!? (1... c5 2. Nf3 (2. c3) d6) 2. Nf3 ?! ; rest of line\n% escaped line\n2... Nc6 *";
+        let game = &parse_pgn(pgn).unwrap()[0];
+        assert_eq!(game.moves.len(), 4);
+        assert_eq!(game.nags, vec![(1, 1), (2, 5), (3, 6)]);
+        assert_eq!(game.comments, vec![(3, "rest of line".to_string())]);
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn multiple_games_and_setup() {
+        let pgn = format!("{}\n[Event \"Endgame\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n\n40... Kd7 41.e4 1/2-1/2\n\n1.d4 d5", OPERA_GAME);
+        let games = parse_pgn(&pgn).unwrap();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games[1].moves.len(), 2);
+        assert_eq!(games[1].result, "1/2-1/2");
+        assert_eq!(games[2].moves.len(), 2);
+        assert_eq!(games[2].result, "*");
+
+        let exported = games[1].to_pgn();
+        assert!(exported.contains("[Event \"Endgame\"]\n[Site \"?\"]"));
+        assert!(exported.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]"));
+        assert!(exported.contains("40... Kd7 41. e4 1/2-1/2"));
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let err = parse_pgn("[Event \"?\"]\n\n1. e4 e5\n2. Nf3 Nf6 3. Ke3").err().unwrap();
+        assert_eq!((err.line, err.column), (4, 15));
+        assert!(err.message.contains("Ke3"), "{}", err);
+
+        let err = parse_pgn("1. e4 {never closed").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 7));
+        let err = parse_pgn("1. e4 (1. d4 e5").err().unwrap();
+        assert_eq!(err.message, "unterminated variation");
+        let err = parse_pgn("[Event \"?]\n1. e4").err().unwrap();
+        assert_eq!(err.line, 1);
+    }
+
+    #[test]
+    fn result_from_status() {
+        assert_eq!(result_for(GameStatus::Checkmate, PieceColor::Black), "1-0");
+        assert_eq!(result_for(GameStatus::Checkmate, PieceColor::White), "0-1");
+        assert_eq!(result_for(GameStatus::Stalemate, PieceColor::White), "1/2-1/2");
+        assert_eq!(result_for(GameStatus::Ongoing, PieceColor::White), "*");
+    }
+}
@@ -0,0 +1,185 @@
+use std::ops::Deref;
+
+use crate::chess::{self, game_status, get_all_valid_moves, in_check_state, make_move_on, FenError, GameStatus, Move, Piece, PieceColor, State, START_FEN};
+use crate::game::Game;
+use crate::san;
+
+/// A chess position together with the positions that led to it, which are
+/// needed to recognise repetitions. Each `Position` is independent, so any
+/// number of games can be played and searched side by side.
+#[derive(Clone)]
+pub struct Position {
+    state: State,
+    history: Vec<u64>, // hashes of the earlier positions, oldest first
+}
+
+/// The legal moves of a position. Derefs to `[Move]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveList(Vec<Move>);
+
+impl Position {
+    /// The initial position of a standard game.
+    pub fn new() -> Self {
+        Position::from(State::from_fen(START_FEN).unwrap())
+    }
+
+    /// Parses Forsyth-Edwards Notation; castling rights may also use
+    /// Shredder-FEN file letters.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        State::from_fen(fen).map(Position::from)
+    }
+
+    pub fn to_fen(&self) -> String {
+        self.state.to_fen()
+    }
+
+    /// The underlying board representation, e.g. for an evaluation hook.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn side_to_move(&self) -> PieceColor {
+        self.state.turn
+    }
+
+    /// The piece on `square`, numbered from 0 for a8 to 63 for h1.
+    pub fn piece_at(&self, square: usize) -> Option<Piece> {
+        self.state.board.get(square).copied().flatten()
+    }
+
+    /// Zobrist hash of the position, equal for equal positions.
+    pub fn hash(&self) -> u64 {
+        self.state.zob_hash
+    }
+
+    /// Hashes of the positions before this one, oldest first.
+    pub fn history(&self) -> &[u64] {
+        &self.history
+    }
+
+    pub fn legal_moves(&self) -> MoveList {
+        MoveList(get_all_valid_moves(&self.state))
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        self.legal_moves().contains(&mv)
+    }
+
+    pub fn in_check(&self) -> bool {
+        in_check_state(&self.state)
+    }
+
+    /// Whether the game is over, and why.
+    pub fn status(&self) -> GameStatus {
+        game_status(&self.state, &self.history)
+    }
+
+    /// Plays `mv` if it is legal and returns whether it was.
+    pub fn play(&mut self, mv: Move) -> bool {
+        if !self.is_legal(mv) {
+            return false;
+        }
+        self.history.push(self.state.zob_hash);
+        make_move_on(mv, &mut self.state);
+        true
+    }
+
+    /// The legal move in coordinate notation, e.g. "e2e4" or "e7e8q".
+    pub fn parse_uci(&self, text: &str) -> Option<Move> {
+        chess::parse_uci_move(&self.state, text)
+    }
+
+    /// The legal move in standard algebraic notation, e.g. "Nf3" or "exd8=Q+".
+    pub fn parse_san(&self, text: &str) -> Option<Move> {
+        san::parse_san(&self.state, text)
+    }
+
+    /// Standard algebraic notation of the legal move `mv`.
+    pub fn to_san(&self, mv: Move) -> String {
+        san::to_san(&self.state, mv)
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::new()
+    }
+}
+
+/// A position without history, as if the game started from `state`.
+impl From<State> for Position {
+    fn from(state: State) -> Self {
+        Position {state, history: Vec::new()}
+    }
+}
+
+/// The current position of a game record, with its history.
+impl From<&Game> for Position {
+    fn from(game: &Game) -> Self {
+        Position {state: game.state().clone(), history: game.history()}
+    }
+}
+
+impl MoveList {
+    pub fn to_uci(&self) -> Vec<String> {
+        self.0.iter().map(|mv| mv.to_uci()).collect()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.0
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = std::vec::IntoIter<Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_legal_moves_only() {
+        let mut position = Position::new();
+        assert_eq!(position.legal_moves().len(), 20);
+        let e4 = position.parse_san("e4").unwrap();
+        assert_eq!(position.parse_uci("e2e4"), Some(e4));
+        assert!(position.play(e4));
+        assert!(!position.play(e4));
+        assert_eq!(position.side_to_move(), PieceColor::Black);
+        assert_eq!(position.history(), &[Position::new().hash()]);
+        assert_eq!(position.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    }
+
+    #[test]
+    fn repetition_needs_history() {
+        let mut position = Position::new();
+        for _ in 0..2 {
+            for uci in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                assert_eq!(position.status(), GameStatus::Ongoing);
+                assert!(position.play(position.parse_uci(uci).unwrap()));
+            }
+        }
+        assert_eq!(position.status(), GameStatus::ThreefoldRepetition);
+        // the same placement without the earlier moves is a fresh game
+        assert_eq!(Position::from_fen(&position.to_fen()).unwrap().status(), GameStatus::Ongoing);
+    }
+}
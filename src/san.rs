@@ -0,0 +1,223 @@
+use crate::chess::{get_all_valid_moves, in_check_state, make_move_on, parse_square, square_name, Move, PieceType, State};
+
+fn piece_letter(tp: PieceType) -> char {
+    match tp {
+        PieceType::Pawn => 'P',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+    }
+}
+
+fn piece_from_letter(c: char) -> Option<PieceType> {
+    match c.to_ascii_uppercase() {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+// Standard algebraic notation of a legal move, e.g. "Nbd7", "exd6", "O-O-O", "e8=Q+"
+pub fn to_san(state: &State, mv: Move) -> String {
+    let Some(piece) = state.board[mv.from()] else {return String::new()};
+    let mut san = String::new();
+
+    if mv.is_castle() {
+        san.push_str(if mv.to() % 8 == 6 {"O-O"} else {"O-O-O"});
+    } else {
+        let (from, to) = (mv.from(), mv.to());
+        if piece.tp == PieceType::Pawn {
+            if mv.is_capture() {
+                san.push(square_name(from).chars().next().unwrap());
+            }
+        } else {
+            san.push(piece_letter(piece.tp));
+            // other pieces of the same type that can also reach `to`
+            let others = get_all_valid_moves(state).into_iter()
+                .filter(|other| other.to() == to && other.from() != from)
+                .filter(|other| state.board[other.from()].is_some_and(|p| p.tp == piece.tp))
+                .map(|other| other.from())
+                .collect::<Vec<usize>>();
+            if !others.is_empty() {
+                let name = square_name(from);
+                if others.iter().all(|&sq| sq % 8 != from % 8) {
+                    san.push_str(&name[0..1]);
+                } else if others.iter().all(|&sq| sq / 8 != from / 8) {
+                    san.push_str(&name[1..2]);
+                } else {
+                    san.push_str(&name);
+                }
+            }
+        }
+        if mv.is_capture() {
+            san.push('x');
+        }
+        san.push_str(&square_name(to));
+        if let Some(tp) = mv.promotion() {
+            san.push('=');
+            san.push(piece_letter(tp));
+        }
+    }
+
+    let mut next = state.clone();
+    make_move_on(mv, &mut next);
+    if in_check_state(&next) {
+        san.push(if get_all_valid_moves(&next).is_empty() {'#'} else {'+'});
+    }
+    san
+}
+
+// SAN of a sequence of moves played from `state`, e.g. a game or a PV
+pub fn to_san_line(state: &State, moves: &[Move]) -> Vec<String> {
+    let mut state = state.clone();
+    moves.iter().map(|&mv| {
+        let san = to_san(&state, mv);
+        make_move_on(mv, &mut state);
+        san
+    }).collect()
+}
+
+// Finds the legal move written in `san`. Check marks, annotations, "e.p.",
+// zeros for castling, a missing "=" or "x" and coordinates such as "Ng1-f3"
+// are all accepted, as long as exactly one legal move fits.
+pub fn parse_san(state: &State, san: &str) -> Option<Move> {
+    let mut s = san.trim().trim_end_matches("e.p.").trim_end();
+    s = s.trim_end_matches(['+', '#', '!', '?']);
+    let moves = get_all_valid_moves(state);
+
+    let castle = s.replace('0', "O");
+    if castle == "O-O" || castle == "O-O-O" {
+        let long = castle == "O-O-O";
+        return moves.into_iter().find(|mv| mv.is_castle() && (mv.to() % 8 == 2) == long);
+    }
+
+    let mut chars = s.chars().filter(|&c| !matches!(c, 'x' | ':' | '-' | '=')).collect::<Vec<char>>();
+
+    // "e8=Q" and "e8Q", but "b8" alone is a square and not a bishop
+    let mut promotion = None;
+    if chars.len() >= 3 && chars[chars.len() - 2].is_ascii_digit() {
+        promotion = Some(piece_from_letter(*chars.last()?)?);
+        chars.pop();
+    }
+
+    let tp = match chars.first() {
+        Some(c) if c.is_ascii_uppercase() => {
+            let tp = piece_from_letter(*c)?;
+            chars.remove(0);
+            tp
+        }
+        _ => PieceType::Pawn,
+    };
+
+    if chars.len() < 2 {return None;}
+    let to = parse_square(&chars[chars.len() - 2..].iter().collect::<String>())?;
+    let hint = &chars[..chars.len() - 2];
+    if hint.len() > 2 {return None;}
+
+    let mut found = None;
+    for mv in moves {
+        let name = square_name(mv.from());
+        if mv.to() != to
+            || mv.promotion() != promotion
+            || state.board[mv.from()].map(|p| p.tp) != Some(tp)
+            || !hint.iter().all(|&c| name.contains(c)) {
+            continue;
+        }
+        if found.is_some() {return None;}
+        found = Some(mv);
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(fen: &str) -> State {
+        State::from_fen(fen).unwrap()
+    }
+
+    // Every legal move must survive a round trip through SAN
+    fn round_trip(state: &State) -> Vec<String> {
+        get_all_valid_moves(state).into_iter().map(|mv| {
+            let san = to_san(state, mv);
+            assert_eq!(parse_san(state, &san), Some(mv), "{}", san);
+            san
+        }).collect()
+    }
+
+    #[test]
+    fn encodes_special_moves() {
+        let kiwipete = state("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let sans = round_trip(&kiwipete);
+        for san in ["O-O", "O-O-O", "Nxf7", "dxe6", "Qxh3", "Bxa6", "Nb1", "gxh3", "Rb1"] {
+            assert!(sans.contains(&san.to_string()), "{} missing from {:?}", san, sans);
+        }
+
+        let promotions = state("3r3k/4P3/8/8/8/8/8/K7 w - - 0 1");
+        let sans = round_trip(&promotions);
+        for san in ["e8=Q+", "e8=R+", "e8=N", "exd8=Q+", "exd8=B"] {
+            assert!(sans.contains(&san.to_string()), "{} missing from {:?}", san, sans);
+        }
+
+        let en_passant = state("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2");
+        assert!(round_trip(&en_passant).contains(&"exd6".to_string()));
+    }
+
+    #[test]
+    fn disambiguation() {
+        // knights on b8 and f6 can both go to d7, rooks on a1 and a5 to a3
+        let position = state("1n2k3/8/5n2/r7/8/8/7K/r7 b - - 0 1");
+        let sans = round_trip(&position);
+        for san in ["Nbd7", "Nfd7", "R5a3", "R1a3", "Ng8"] {
+            assert!(sans.contains(&san.to_string()), "{} missing from {:?}", san, sans);
+        }
+        // three queens where neither file nor rank alone is enough
+        let queens = state("8/8/1k6/8/4Q2Q/8/8/K6Q w - - 0 1");
+        assert!(round_trip(&queens).contains(&"Qh4e1".to_string()));
+    }
+
+    #[test]
+    fn check_and_mate() {
+        let position = state("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        let mate = parse_san(&position, "Ra8").unwrap();
+        assert_eq!(to_san(&position, mate), "Ra8#");
+        let position = state("6k1/5pp1/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        assert_eq!(to_san(&position, mate), "Ra8+");
+    }
+
+    #[test]
+    fn tolerant_parsing() {
+        let start = state("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let nf3 = parse_san(&start, "Nf3");
+        assert!(nf3.is_some());
+        for san in ["Nf3+", "Nf3!?", "Ngf3", "Ng1f3", "Ng1-f3", " Nf3 "] {
+            assert_eq!(parse_san(&start, san), nf3, "{}", san);
+        }
+        assert_eq!(parse_san(&start, "e4").map(|mv| mv.to()), Some(36));
+        assert_eq!(parse_san(&start, "Nd2"), None);
+        assert_eq!(parse_san(&start, "Qh5"), None);
+        assert_eq!(parse_san(&start, "e5"), None);
+        assert_eq!(parse_san(&start, ""), None);
+        assert_eq!(parse_san(&start, "O-O"), None);
+
+        let castles = state("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert_eq!(parse_san(&castles, "0-0").map(|mv| mv.to()), Some(62));
+        assert_eq!(parse_san(&castles, "O-O-O+").map(|mv| mv.to()), Some(58));
+
+        let en_passant = state("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2");
+        assert!(parse_san(&en_passant, "exd6 e.p.").is_some_and(|mv| mv.is_en_passant()));
+        assert!(parse_san(&en_passant, "ed6").is_some_and(|mv| mv.is_en_passant()));
+
+        let promotion = state("3r3k/4P3/8/8/8/8/8/K7 w - - 0 1");
+        assert!(parse_san(&promotion, "e8Q").is_some_and(|mv| mv.promotion() == Some(PieceType::Queen)));
+        assert!(parse_san(&promotion, "exd8=N").is_some_and(|mv| mv.promotion() == Some(PieceType::Knight)));
+        // the piece has to be named
+        assert_eq!(parse_san(&promotion, "e8"), None);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::chess::{Piece, PieceType, PieceColor};
+use crate::bitboard::squares;
+use crate::chess::{PieceType, PieceColor, State};
 
 const WHITE_KNIGHT_BONUS: [i32;64] = [
     -50,-40,-30,-30,-30,-30,-40,-50,
@@ -137,65 +138,65 @@ const KNIGHT_VALUE: i32 = 300;
 const BISHOP_VALUE: i32 = 300;
 const ROOK_VALUE: i32 = 500;
 const QUEEN_VALUE: i32 = 900;
-const KING_VALUE: i32 = 1000000;
+pub fn piece_value(tp: PieceType) -> i32 {
+    match tp {
+        PieceType::Pawn => PAWN_VALUE,
+        PieceType::Knight => KNIGHT_VALUE,
+        PieceType::Bishop => BISHOP_VALUE,
+        PieceType::Rook => ROOK_VALUE,
+        PieceType::Queen => QUEEN_VALUE,
+        PieceType::King => 0, // never captured, mate is found by the search
+    }
+}
+
 
 
+fn bonus_tables(tp: PieceType) -> (&'static [i32; 64], &'static [i32; 64]) {
+    match tp {
+        PieceType::Pawn => (&WHITE_PAWN_BONUS, &BLACK_PAWN_BONUS),
+        PieceType::Knight => (&WHITE_KNIGHT_BONUS, &BLACK_KNIGHT_BONUS),
+        PieceType::Bishop => (&WHITE_BISHOP_BONUS, &BLACK_BISHOP_BONUS),
+        PieceType::Rook => (&WHITE_ROOK_BONUS, &BLACK_ROOK_BONUS),
+        PieceType::Queen => (&WHITE_QUEEN_BONUS, &BLACK_QUEEN_BONUS),
+        PieceType::King => (&WHITE_KING_BONUS, &BLACK_KING_BONUS),
+    }
+}
+
+/// Static evaluation used by the search, in centipawns from the point of view
+/// of the side to move. Plug in your own with `Engine::set_evaluator`; any
+/// `Fn(&State) -> i32` closure works too.
+pub trait Evaluator: Send {
+    fn evaluate(&self, state: &State) -> i32;
+}
+
+impl<F: Fn(&State) -> i32 + Send> Evaluator for F {
+    fn evaluate(&self, state: &State) -> i32 {
+        self(state)
+    }
+}
+
+/// The built-in evaluation: material plus piece-square bonuses, see `evaluate`.
+#[derive(Clone, Copy, Default)]
+pub struct PieceSquareEvaluator;
+
+impl Evaluator for PieceSquareEvaluator {
+    fn evaluate(&self, state: &State) -> i32 {
+        evaluate(state)
+    }
+}
 
-pub fn evaluate(board: [Option<Piece>; 64], turn: PieceColor) -> i32 {
+// Material and piece-square bonuses, from the side to move's point of view
+pub fn evaluate(state: &State) -> i32 {
     let mut eval = 0;
 
-    for i in 0..64 {
-        if let Some(piece) = board[i] {
-            match piece.tp {
-                PieceType::Pawn => {
-                    if piece.color == PieceColor::White {
-                        eval += PAWN_VALUE + WHITE_PAWN_BONUS[i];
-                    } else {
-                        eval -= PAWN_VALUE + BLACK_PAWN_BONUS[i];
-                    }
-                }
-
-                PieceType::Knight => {
-                    if piece.color == PieceColor::White {
-                        eval += KNIGHT_VALUE + WHITE_KNIGHT_BONUS[i];
-                    } else {
-                        eval -= KNIGHT_VALUE + BLACK_KNIGHT_BONUS[i];
-                    }
-                }
-
-                PieceType::Bishop => {
-                    if piece.color == PieceColor::White {
-                        eval += BISHOP_VALUE + WHITE_BISHOP_BONUS[i];
-                    } else {
-                        eval -= BISHOP_VALUE + BLACK_BISHOP_BONUS[i];
-                    }
-                }
-
-                PieceType::Rook => {
-                    if piece.color == PieceColor::White {
-                        eval += ROOK_VALUE + WHITE_ROOK_BONUS[i];
-                    } else {
-                        eval -= ROOK_VALUE + BLACK_ROOK_BONUS[i];
-                    }
-                }
-
-                PieceType::Queen => {
-                    if piece.color == PieceColor::White {
-                        eval += QUEEN_VALUE + WHITE_QUEEN_BONUS[i];
-                    } else {
-                        eval -= QUEEN_VALUE + BLACK_QUEEN_BONUS[i];
-                    }
-                }
-
-                PieceType::King => {
-                    if piece.color == PieceColor::White {
-                        eval += KING_VALUE + WHITE_KING_BONUS[i];
-                    } else {
-                        eval -= KING_VALUE + BLACK_KING_BONUS[i];
-                    }
-                }
-            }
-        } else {}
+    for tp in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King] {
+        let (white_bonus, black_bonus) = bonus_tables(tp);
+        for i in squares(state.pieces_of(tp, PieceColor::White)) {
+            eval += piece_value(tp) + white_bonus[i];
+        }
+        for i in squares(state.pieces_of(tp, PieceColor::Black)) {
+            eval -= piece_value(tp) + black_bonus[i];
+        }
     }
-    if turn == PieceColor::White {eval} else {-eval}
-}
\ No newline at end of file
+    if state.turn == PieceColor::White {eval} else {-eval}
+}
@@ -1,87 +1,573 @@
-use std::i32;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::chess::{self, in_check_state, make_move_on, State};
-use crate::score::{evaluate};
+use crate::chess::{self, in_check_state, king_attacked, make_move_on, unmake_move, Move, State};
+use crate::chess::PieceType;
+use crate::score::{piece_value, Evaluator, PieceSquareEvaluator};
+use crate::tt::{Bound, TranspositionTable};
 
-pub fn find_move(state: &State, depth: usize) -> ((usize, usize), i32) {
-    if depth == 0 {
-        return ((64, 64), evaluate(state.board, state.turn));
+const MAX_DEPTH: usize = 64;
+const MAX_PLY: usize = 128;
+// how often (in nodes) the clock is looked at
+const CHECK_INTERVAL: u64 = 2048;
+// slack for positional gains when delta pruning captures in quiescence
+const DELTA_MARGIN: i32 = 200;
+
+// Being checkmated `ply` half moves from the root scores -(MATE - ply), so
+// shorter mates score higher. Anything beyond MATE_BOUND is a mate score.
+pub const MATE: i32 = 100_000;
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+const INFINITY: i32 = MATE + 1;
+
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub depth: Option<usize>,
+    pub movetime: Option<u64>, // milliseconds
+    pub nodes: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SearchInfo {
+    pub depth: usize,
+    pub score: i32,
+    pub nodes: u64,
+    pub time: u64, // milliseconds
+    pub pv: Vec<Move>,
+}
+
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: i32,
+    pub pv: Vec<Move>, // starts with best_move
+    pub iterations: Vec<SearchInfo>,
+}
+
+pub struct Searcher {
+    pub tt: TranspositionTable,
+    pub nodes: u64,
+    pub stop: Arc<AtomicBool>, // set from another thread to abort the search
+    pub evaluator: Box<dyn Evaluator>,
+    limits: Limits,
+    start: f64,
+    stopped: bool,
+    killers: [[Move; 2]; MAX_PLY], // quiet moves that caused a cutoff, per ply
+    history: [[u32; 64]; 64], // [from][to] cutoff score of quiet moves
+    hashes: Vec<u64>, // positions from the start of the game down to the current node
+    // triangular PV table: pv[ply][ply..pv_len[ply]] is the best line found from `ply`
+    pv: Box<[[Move; MAX_PLY]; MAX_PLY]>,
+    pv_len: [usize; MAX_PLY],
+}
+
+// Ordering scores, highest searched first
+const HASH_MOVE_SCORE: u32 = 1 << 30;
+const CAPTURE_SCORE: u32 = 1 << 29;
+const KILLER_SCORE: u32 = 1 << 28;
+
+// Moves until mate for the side to move, negative when it is getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score > MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score < -MATE_BOUND {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+// Mate scores are stored relative to the node rather than the root, so that
+// a transposition found at another ply still gets the right mate distance
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score + ply as i32
+    } else if score < -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score - ply as i32
+    } else if score < -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+impl Searcher {
+    pub fn new(tt_size_mb: usize) -> Self {
+        Searcher {
+            tt: TranspositionTable::new(tt_size_mb),
+            nodes: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+            evaluator: Box::new(PieceSquareEvaluator),
+            limits: Limits::default(),
+            start: 0.0,
+            stopped: false,
+            killers: [[Move::NULL; 2]; MAX_PLY],
+            history: [[0; 64]; 64],
+            hashes: Vec::new(),
+            pv: Box::new([[Move::NULL; MAX_PLY]; MAX_PLY]),
+            pv_len: [0; MAX_PLY],
+        }
     }
 
-    let mut best_move = (64,64);
-    let mut max = i32::MIN+1;
+    // Iterative deepening: search depth 1, 2, 3... until a limit runs out and
+    // return the best move of the last iteration that finished.
+    // `history` holds the hashes of the game positions before `state`, for repetitions.
+    // `report` is called after every completed iteration.
+    pub fn search(&mut self, state: &State, history: &[u64], limits: Limits, mut report: impl FnMut(&SearchInfo)) -> SearchResult {
+        self.limits = limits;
+        self.nodes = 0;
+        self.start = now_ms();
+        self.stopped = false;
+        self.killers = [[Move::NULL; 2]; MAX_PLY];
+        self.history = [[0; 64]; 64];
+        self.hashes = history.to_vec();
+        self.hashes.push(state.zob_hash);
 
-    let mut move_possible = false;
+        let mut result = SearchResult {
+            best_move: Move::NULL,
+            score: 0,
+            pv: Vec::new(),
+            iterations: Vec::new(),
+        };
 
-    
-    for &(from, to) in chess::get_all_valid_moves(state).iter() {
-        println!("{} {}", from, to);
-        move_possible = true;
-        let mut temp_state = state.clone();
-        make_move_on(from, to, &mut temp_state);
+        // a search stopped before finishing its first root move still returns a legal move
+        let mut moves = chess::get_all_valid_moves(state);
+        let hash_move = self.tt.probe(state.zob_hash).map_or(Move::NULL, |entry| entry.best_move);
+        self.order_moves(state, &mut moves, hash_move, 0);
+        if let Some(&mv) = moves.first() {
+            result.best_move = mv;
+            result.pv = vec![mv];
+        }
 
-        // let eval = -nega_max(&temp_state, depth-1);
+        let mut root = state.clone();
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+        for depth in 1..=max_depth {
+            let (best_move, score) = self.search_root(&mut root, depth);
+            if self.stopped {
+                // an unfinished first iteration is still better than no move at all
+                if result.iterations.is_empty() && best_move != Move::NULL {
+                    result.best_move = best_move;
+                    result.score = score;
+                    result.pv = vec![best_move];
+                }
+                break;
+            }
 
-        let eval = -alpha_beta(&temp_state, i32::MIN+1, -max, depth);
+            result.best_move = best_move;
+            result.score = score;
+            result.pv = self.pv_line(state, depth);
+            let info = SearchInfo {
+                depth,
+                score,
+                nodes: self.nodes,
+                time: self.elapsed(),
+                pv: result.pv.clone(),
+            };
+            report(&info);
+            result.iterations.push(info);
+            if best_move == Move::NULL {break;}
+        }
+        result
+    }
 
-        if eval>max {
-            max = eval;
-            best_move = (from, to);
+    fn elapsed(&self) -> u64 {
+        (now_ms() - self.start).max(0.0) as u64
+    }
+
+    fn check_limits(&mut self) {
+        if let Some(nodes) = self.limits.nodes
+            && self.nodes >= nodes {
+            self.stopped = true;
+        }
+        if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            if self.stop.load(Ordering::Relaxed) {
+                self.stopped = true;
+            }
+            if let Some(movetime) = self.limits.movetime
+                && self.elapsed() >= movetime {
+                self.stopped = true;
+            }
         }
     }
 
-    if !move_possible {
-        if in_check_state(state) {
-            max += 1;
-        } else {
-            max = 0;
+    // Any earlier occurrence of the current position, in the game or the search
+    // tree, is scored as a draw: if repeating were good it can be repeated again
+    fn is_repetition(&self) -> bool {
+        let (&hash, earlier) = self.hashes.split_last().unwrap();
+        earlier.iter().rev().skip(1).step_by(2).any(|&h| h == hash)
+    }
+
+    fn make(&mut self, mv: Move, state: &mut State) -> chess::Undo {
+        let undo = make_move_on(mv, state);
+        self.hashes.push(state.zob_hash);
+        undo
+    }
+
+    fn unmake(&mut self, mv: Move, undo: &chess::Undo, state: &mut State) {
+        self.hashes.pop();
+        unmake_move(mv, undo, state);
+    }
+
+    // Records `mv` followed by the child's line as the best line from `ply`
+    fn update_pv(&mut self, ply: usize, mv: Move) {
+        let child_len = self.pv_len[ply + 1].max(ply + 1);
+        self.pv[ply][ply] = mv;
+        for i in ply + 1..child_len {
+            self.pv[ply][i] = self.pv[ply + 1][i];
         }
+        self.pv_len[ply] = child_len;
     }
-    (best_move, max)
-}
 
-fn nega_max(state: &State, depth: usize) -> i32 {
-    if depth==0 {return evaluate(state.board, state.turn)}
+    // The root line of the PV table, continued with the stored best moves of the
+    // transposition table where a hash cutoff cut it short
+    fn pv_line(&self, state: &State, max_len: usize) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut state = state.clone();
+        let mut table = self.pv[0][..self.pv_len[0]].iter();
+        while pv.len() < max_len {
+            let mv = match table.next() {
+                Some(&mv) => mv,
+                None => match self.tt.probe(state.zob_hash) {
+                    Some(entry) => entry.best_move,
+                    None => break,
+                },
+            };
+            if !chess::get_valid_moves_for(mv.from(), &state).contains(&mv) {break;}
+            make_move_on(mv, &mut state);
+            pv.push(mv);
+        }
+        pv
+    }
+
+    fn search_root(&mut self, state: &mut State, depth: usize) -> (Move, i32) {
+        let mut best_move = Move::NULL;
+        let mut max = -INFINITY;
+        self.pv_len[0] = 0;
+
+        let mut moves = chess::get_all_valid_moves(state);
+        if moves.is_empty() {
+            return (best_move, if in_check_state(state) {-MATE} else {0});
+        }
+
+        // previous iteration's best move goes first
+        let hash_move = self.tt.probe(state.zob_hash).map_or(Move::NULL, |entry| entry.best_move);
+        self.order_moves(state, &mut moves, hash_move, 0);
+
+        for &mv in moves.iter() {
+            let undo = self.make(mv, state);
+            let eval = -self.alpha_beta(state, -INFINITY, -max, depth-1, 1);
+            self.unmake(mv, &undo, state);
+            if self.stopped {break;}
+
+            if eval>max {
+                max = eval;
+                best_move = mv;
+                self.update_pv(0, mv);
+            }
+        }
+
+        if !self.stopped {
+            self.tt.store(state.zob_hash, depth, Bound::Exact, max, best_move);
+        }
+        (best_move, max)
+    }
+
+    fn alpha_beta(&mut self, state: &mut State, mut alpha: i32, mut beta: i32, depth: usize, ply: usize) -> i32 {
+        self.pv_len[ply] = ply;
+        if self.is_repetition() || chess::is_insufficient_material(state) {return 0;}
+        // a mate on the hundredth half-move still counts, only otherwise is it a draw
+        if state.halfmove_clock >= 100 {
+            if !chess::get_all_valid_moves(state).is_empty() {return 0;}
+            return if in_check_state(state) {-MATE + ply as i32} else {0};
+        }
+        if depth == 0 {return self.quiescence(state, alpha, beta, ply)}
+
+        self.nodes += 1;
+        self.check_limits();
+        if self.stopped {return 0;}
+
+        let (alpha_orig, beta_orig) = (alpha, beta);
+
+        let mut hash_move = Move::NULL;
+        if let Some(entry) = self.tt.probe(state.zob_hash) {
+            if entry.depth as usize >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower => alpha = alpha.max(score),
+                    Bound::Upper => beta = beta.min(score),
+                }
+                if alpha >= beta {return score;}
+            }
+            hash_move = entry.best_move;
+        }
+
+        let mut max = -INFINITY;
+        let mut best_move = Move::NULL;
+        let mut legal_moves = 0;
+        let mut moves = chess::get_all_valid_moves_raw(state);
+        self.order_moves(state, &mut moves, hash_move, ply);
+        for &mv in moves.iter() {
+            let undo = self.make(mv, state);
+            if king_attacked(!state.turn, state) {
+                self.unmake(mv, &undo, state);
+                continue;
+            }
+            legal_moves += 1;
+            let eval = -self.alpha_beta(state, -beta, -alpha, depth-1, ply+1);
+            self.unmake(mv, &undo, state);
+            if self.stopped {return 0;}
+            if eval>max {
+                max = eval;
+                best_move = mv;
+            }
+            if eval>alpha {
+                alpha = eval;
+                self.update_pv(ply, mv);
+            }
+            if eval>=beta {
+                if !mv.is_capture() {
+                    self.store_killer(mv, ply);
+                    // saturating, a long search can pile up more than a u32 holds
+                    let bonus = (depth * depth) as u32;
+                    self.history[mv.from()][mv.to()] = self.history[mv.from()][mv.to()].saturating_add(bonus);
+                }
+                break;
+            }
+        }
+        if legal_moves == 0 {
+            return if in_check_state(state) {-MATE + ply as i32} else {0};
+        }
+
+        let bound = if max <= alpha_orig {
+            Bound::Upper
+        } else if max >= beta_orig {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.tt.store(state.zob_hash, depth, bound, score_to_tt(max, ply), best_move);
+        max
+    }
+
+    fn store_killer(&mut self, mv: Move, ply: usize) {
+        if ply >= MAX_PLY || self.killers[ply][0] == mv {return;}
+        self.killers[ply][1] = self.killers[ply][0];
+        self.killers[ply][0] = mv;
+    }
+
+    // Hash move first, then captures by MVV-LVA, then killers, then quiet moves by history
+    fn order_moves(&self, state: &State, moves: &mut [Move], hash_move: Move, ply: usize) {
+        let killers = if ply < MAX_PLY {self.killers[ply]} else {[Move::NULL; 2]};
+        moves.sort_by_cached_key(|&mv| {
+            let score = if mv == hash_move {
+                HASH_MOVE_SCORE
+            } else if mv.is_capture() {
+                CAPTURE_SCORE + mvv_lva(state, mv)
+            } else if mv == killers[0] {
+                KILLER_SCORE + 1
+            } else if mv == killers[1] {
+                KILLER_SCORE
+            } else {
+                self.history[mv.from()][mv.to()].min(KILLER_SCORE - 1)
+            };
+            std::cmp::Reverse(score)
+        });
+    }
+
+    // Keep resolving captures and promotions past the horizon so that the
+    // static evaluation is only taken in quiet positions
+    fn quiescence(&mut self, state: &mut State, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.nodes += 1;
+        self.check_limits();
+        if self.stopped {return 0;}
+
+        // in check standing pat is not an option, every evasion gets searched
+        if in_check_state(state) {
+            return self.quiescence_evasions(state, alpha, beta, ply);
+        }
+
+        let stand_pat = self.evaluator.evaluate(state);
+        if stand_pat >= beta {return stand_pat;}
+        if stand_pat > alpha {alpha = stand_pat;}
+
+        let mut max = stand_pat;
+        let mut moves = chess::get_all_captures_raw(state);
+        moves.sort_by_key(|&mv| std::cmp::Reverse(mvv_lva(state, mv)));
+        for &mv in moves.iter() {
+            // delta pruning: skip captures that can't raise alpha even with a margin
+            let mut gain = match state.board[mv.to()] {
+                Some(p) => piece_value(p.tp),
+                None if mv.is_en_passant() => piece_value(PieceType::Pawn),
+                None => 0,
+            };
+            if let Some(tp) = mv.promotion() {
+                gain += piece_value(tp) - piece_value(PieceType::Pawn);
+            }
+            if stand_pat + gain + DELTA_MARGIN < alpha {continue;}
+
+            let undo = make_move_on(mv, state);
+            if king_attacked(!state.turn, state) {
+                unmake_move(mv, &undo, state);
+                continue;
+            }
+            let eval = -self.quiescence(state, -beta, -alpha, ply+1);
+            unmake_move(mv, &undo, state);
+            if self.stopped {return 0;}
+            if eval>max {max = eval;}
+            if eval>alpha {alpha = eval;}
+            if eval>=beta {break;}
+        }
+        max
+    }
 
-    let mut max = i32::MIN;
-    for &(from, to) in chess::get_all_valid_moves(state).iter() {
-        let mut temp_state = state.clone();
-        make_move_on(from, to, &mut temp_state);
-        let eval = -nega_max(&temp_state, depth - 1);
-        if max<eval {
-            max = eval;
+    fn quiescence_evasions(&mut self, state: &mut State, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        let mut max = -INFINITY;
+        let mut moves = chess::get_all_valid_moves_raw(state);
+        moves.sort_by_key(|&mv| std::cmp::Reverse(if mv.is_capture() {mvv_lva(state, mv)} else {0}));
+        for &mv in moves.iter() {
+            let undo = make_move_on(mv, state);
+            if king_attacked(!state.turn, state) {
+                unmake_move(mv, &undo, state);
+                continue;
+            }
+            let eval = -self.quiescence(state, -beta, -alpha, ply+1);
+            unmake_move(mv, &undo, state);
+            if self.stopped {return 0;}
+            if eval>max {max = eval;}
+            if eval>alpha {alpha = eval;}
+            if eval>=beta {break;}
         }
+        if max == -INFINITY {-MATE + ply as i32} else {max}
     }
-    max
 }
 
-// fn alpha_beta(state: &State, depth: usize) {
-//     let max = i32::MIN;
-//     let mut best_move = (64,64);
-
-// }
-
-fn alpha_beta(state: &State, mut alpha: i32, beta: i32, depth: usize) -> i32 {
-    if depth == 0 {return evaluate(state.board, state.turn)}
-
-
-    let mut max = i32::MIN+1;
-    let moves = chess::get_all_valid_moves_raw(state);
-    for &(from, to) in moves.iter() {
-        let mut temp_state = state.clone();
-        make_move_on(from, to, &mut temp_state);
-        let eval = -alpha_beta(&temp_state, -beta, -alpha, depth-1);
-        if eval>alpha {alpha = eval;}
-        if eval>max {max = eval;}
-        if eval>=beta {return eval;}
-    }
-    // if moves.len()==0 {
-    //     if in_check_state(state) {
-    //         max += 1;
-    //     } else {
-    //         max = 0;
-    //     }
-    // }
-    max
+// Most valuable victim, least valuable attacker
+fn mvv_lva(state: &State, mv: Move) -> u32 {
+    let victim = state.board[mv.to()].map_or(PieceType::Pawn, |p| p.tp) as u32;
+    let attacker = state.board[mv.from()].map_or(PieceType::Pawn, |p| p.tp) as u32;
+    (victim + 1) * 8 - attacker
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pv_is_a_legal_line_from_the_best_move() {
+        let state = State::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut searcher = Searcher::new(1);
+        let res = searcher.search(&state, &[], Limits {depth: Some(4), ..Limits::default()}, |_| {});
+        assert_eq!(res.pv.first(), Some(&res.best_move));
+        assert!(res.pv.len() >= 2);
+        assert_eq!(res.iterations.last().unwrap().pv, res.pv);
+
+        let mut state = state.clone();
+        for &mv in &res.pv {
+            assert!(chess::get_all_valid_moves(&state).contains(&mv), "{:?}", res.pv);
+            make_move_on(mv, &mut state);
+        }
+    }
+
+    fn search_depth(fen: &str, depth: usize) -> SearchResult {
+        let state = State::from_fen(fen).unwrap();
+        Searcher::new(1).search(&state, &[], Limits {depth: Some(depth), ..Limits::default()}, |_| {})
+    }
+
+    #[test]
+    fn finds_the_shortest_mate() {
+        // Ra7 then Rb8#, deep enough to also see slower mates
+        let res = search_depth("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 5);
+        assert_eq!(mate_in(res.score), Some(2));
+
+        // Qf8# rather than Qf7 stalemate
+        let res = search_depth("7k/8/6K1/8/8/8/8/5Q2 w - - 0 1", 3);
+        assert_eq!(res.best_move.to_uci(), "f1f8");
+        assert_eq!(mate_in(res.score), Some(1));
+
+        // the mate sits past the horizon of a depth 1 search, in quiescence
+        let res = search_depth("7k/8/6K1/8/8/8/8/5Q2 w - - 0 1", 1);
+        assert_eq!(mate_in(res.score), Some(1));
+
+        let res = search_depth("7k/7Q/6K1/8/8/8/8/8 b - - 0 1", 3);
+        assert_eq!(res.score, -MATE);
+        assert_eq!(mate_in(res.score), Some(0));
+        assert_eq!(mate_in(-MATE + 3), Some(-1));
+    }
+
+    #[test]
+    fn mate_beats_the_fifty_move_rule() {
+        // Qf8# is the hundredth half-move, every other move draws
+        let res = search_depth("7k/8/6K1/8/8/8/8/5Q2 w - - 99 80", 3);
+        assert_eq!(res.best_move.to_uci(), "f1f8");
+        assert_eq!(mate_in(res.score), Some(1));
+        let res = search_depth("7k/8/6K1/8/8/8/8/5Q2 w - - 99 80", 1);
+        assert_eq!(mate_in(res.score), Some(1));
+
+        let res = search_depth("5Q1k/8/6K1/8/8/8/8/8 b - - 100 80", 3);
+        assert_eq!(res.score, -MATE);
+    }
+
+    #[test]
+    fn stopped_search_still_has_a_move() {
+        let state = State::from_fen(chess::START_FEN).unwrap();
+        let res = Searcher::new(1).search(&state, &[], Limits {nodes: Some(1), ..Limits::default()}, |_| {});
+        assert!(res.best_move != Move::NULL);
+        assert!(chess::get_all_valid_moves(&state).contains(&res.best_move));
+        assert_eq!(res.pv, [res.best_move]);
+    }
+
+    #[test]
+    fn move_ordering() {
+        let state = State::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut searcher = Searcher::new(1);
+        let uci = |text: &str| chess::parse_uci_move(&state, text).unwrap();
+        let hash_move = uci("a2a3");
+        searcher.store_killer(uci("e1g1"), 3);
+        searcher.store_killer(uci("d5d6"), 3);
+        searcher.history[uci("b2b3").from()][uci("b2b3").to()] = 50;
+        searcher.history[uci("g2g3").from()][uci("g2g3").to()] = u32::MAX;
+
+        let mut moves = chess::get_all_valid_moves(&state);
+        searcher.order_moves(&state, &mut moves, hash_move, 3);
+        let captures = moves.iter().filter(|mv| mv.is_capture()).count();
+        assert_eq!(moves[0], hash_move);
+        assert!(moves[1..=captures].iter().all(|mv| mv.is_capture()));
+        // the most valuable victims first, cheapest attacker breaking ties
+        assert!(moves[1..=captures].windows(2).all(|w| mvv_lva(&state, w[0]) >= mvv_lva(&state, w[1])));
+        assert_eq!(moves[1], uci("e2a6"));
+        // the newest killer first, then quiet moves by history, even a saturated one
+        assert_eq!(moves[captures + 1..captures + 5], [uci("d5d6"), uci("e1g1"), uci("g2g3"), uci("b2b3")]);
+
+        // without a hash move the captures lead, and killers of another ply don't count
+        let mut moves = chess::get_all_valid_moves(&state);
+        searcher.order_moves(&state, &mut moves, Move::NULL, 4);
+        assert!(moves[..captures].iter().all(|mv| mv.is_capture()));
+        assert_eq!(moves[captures..captures + 2], [uci("g2g3"), uci("b2b3")]);
+    }
+
+    #[test]
+    fn stalemate_is_a_draw() {
+        let res = search_depth("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 3);
+        assert_eq!(res.best_move, Move::NULL);
+        assert_eq!(res.score, 0);
+        assert_eq!(mate_in(res.score), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::mem::size_of;
+
+use crate::chess::Move;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower, // score >= stored score (beta cutoff)
+    Upper, // score <= stored score (failed low)
+}
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: i32,
+    pub best_move: Move, // Move::NULL when there is no move
+}
+
+const EMPTY: Entry = Entry {
+    key: 0,
+    depth: 0,
+    bound: Bound::Upper,
+    score: 0,
+    best_move: Move::NULL,
+};
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+// Larger sizes are clamped, which also keeps the byte count within a 32-bit usize on wasm
+pub const MAX_SIZE_MB: usize = 1024;
+
+pub struct TranspositionTable {
+    entries: Vec<Entry>,
+    mask: usize,
+}
+
+// The largest power of two number of entries that fits in the budget
+fn capacity(size_mb: usize) -> usize {
+    let budget = size_mb.clamp(1, MAX_SIZE_MB) * 1024 * 1024 / size_of::<Entry>();
+    if budget.is_power_of_two() {budget} else {budget.next_power_of_two() / 2}
+}
+
+impl TranspositionTable {
+    // `size_mb` is clamped to 1..=MAX_SIZE_MB
+    pub fn new(size_mb: usize) -> Self {
+        let len = capacity(size_mb);
+        TranspositionTable {
+            entries: vec![EMPTY; len],
+            mask: len - 1,
+        }
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<&Entry> {
+        let entry = &self.entries[hash as usize & self.mask];
+        if entry.key == hash && hash != 0 {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    pub fn store(&mut self, hash: u64, depth: usize, bound: Bound, score: i32, best_move: Move) {
+        let entry = &mut self.entries[hash as usize & self.mask];
+
+        // Keep deeper results for the same position, but always replace a different position
+        if entry.key == hash && entry.depth as usize > depth && bound != Bound::Exact {
+            return;
+        }
+        *entry = Entry {
+            key: hash,
+            depth: depth.min(u8::MAX as usize) as u8,
+            bound,
+            score,
+            best_move,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::QUIET;
+
+    #[test]
+    fn sizes_are_clamped() {
+        assert!(capacity(1).is_power_of_two());
+        assert!(capacity(1) * size_of::<Entry>() <= 1024 * 1024);
+        assert_eq!(capacity(0), capacity(1));
+        assert_eq!(capacity(usize::MAX), capacity(MAX_SIZE_MB));
+        assert_eq!(capacity(3), capacity(2));
+    }
+
+    #[test]
+    fn store_and_probe() {
+        let mut tt = TranspositionTable::new(1);
+        let mv = Move::new(52, 36, QUIET);
+        assert!(tt.probe(42).is_none());
+        for (hash, bound) in [(42, Bound::Exact), (43, Bound::Lower), (44, Bound::Upper)] {
+            tt.store(hash, 3, bound, -25, mv);
+            let entry = tt.probe(hash).unwrap();
+            assert!(entry.bound == bound);
+            assert_eq!((entry.depth, entry.score, entry.best_move), (3, -25, mv));
+        }
+        // another position in the same slot isn't mistaken for the stored one
+        assert!(tt.probe(42 | 1 << 63).is_none());
+        // hash 0 is what an empty slot holds
+        tt.store(0, 3, Bound::Exact, 10, mv);
+        assert!(tt.probe(0).is_none());
+    }
+
+    #[test]
+    fn replacement() {
+        let mut tt = TranspositionTable::new(1);
+        let mv = Move::new(52, 36, QUIET);
+        tt.store(42, 5, Bound::Lower, 100, mv);
+        // a shallower bound for the same position keeps the deeper result
+        tt.store(42, 2, Bound::Upper, 0, Move::NULL);
+        assert_eq!(tt.probe(42).unwrap().depth, 5);
+        // an exact score always goes in, as does a deeper one
+        tt.store(42, 2, Bound::Exact, 7, Move::NULL);
+        assert_eq!((tt.probe(42).unwrap().depth, tt.probe(42).unwrap().score), (2, 7));
+        tt.store(42, 4, Bound::Upper, -3, mv);
+        assert_eq!(tt.probe(42).unwrap().depth, 4);
+        // a different position takes the slot whatever its depth
+        tt.store(42 | 1 << 63, 1, Bound::Lower, 9, mv);
+        assert!(tt.probe(42).is_none());
+        assert_eq!(tt.probe(42 | 1 << 63).unwrap().score, 9);
+        // depths beyond u8 are capped
+        tt.store(42, 300, Bound::Exact, 0, mv);
+        assert_eq!(tt.probe(42).unwrap().depth, u8::MAX);
+    }
+}
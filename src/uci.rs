@@ -0,0 +1,336 @@
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use ::chess::chess::{self, make_move_on, Move, PieceColor, State, START_FEN};
+use ::chess::perft::perft_divide;
+use ::chess::search::{mate_in, Limits, SearchInfo, Searcher, now_ms};
+use ::chess::tt::{self, TranspositionTable};
+
+const BENCH_DEPTH: usize = 5;
+
+// Fixed positions for comparing node counts between engine versions
+const BENCH_FENS: [&str; 4] = [
+    START_FEN,
+    "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+];
+
+struct Uci {
+    state: State,
+    history: Vec<u64>, // hashes of the positions before `state`
+    searcher: Option<Searcher>,
+    hash_mb: usize,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<Searcher>>,
+}
+
+pub fn run() {
+    let mut uci = Uci::new();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {break};
+        if !uci.handle(&line) {break;}
+    }
+    uci.stop_search();
+}
+
+fn format_info(info: &SearchInfo) -> String {
+    let nps = info.nodes * 1000 / info.time.max(1);
+    let pv = info.pv.iter().map(|&mv| mv.to_uci()).collect::<Vec<String>>();
+    let score = match mate_in(info.score) {
+        Some(n) => format!("mate {}", n),
+        None => format!("cp {}", info.score),
+    };
+    format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth, score, info.nodes, nps, info.time, pv.join(" ")
+    )
+}
+
+pub fn print_divide(state: &State, depth: usize) {
+    let start = now_ms();
+    let divide = perft_divide(state, depth);
+    for &(mv, nodes) in divide.iter() {
+        println!("{}: {}", mv.to_uci(), nodes);
+    }
+    let total = divide.iter().map(|&(_, nodes)| nodes).sum::<u64>();
+    let time = (now_ms() - start) as u64;
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {} ms ({} nps)", time, total * 1000 / time.max(1));
+}
+
+// Share of the remaining clock to spend on this move
+fn time_budget(time: u64, inc: u64, movestogo: Option<u64>) -> u64 {
+    let budget = time / movestogo.unwrap_or(30).max(1) + inc * 3 / 4;
+    budget.min(time.saturating_sub(50)).max(1)
+}
+
+// The limits of "go <args>" for the side `turn`, and whether it was "go infinite"
+fn go_limits(args: &[&str], turn: PieceColor) -> (Limits, bool) {
+    let mut limits = Limits::default();
+    let (mut wtime, mut btime, mut winc, mut binc) = (None, None, 0, 0);
+    let mut movestogo = None;
+    let mut infinite = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+        match args[i] {
+            "infinite" => infinite = true,
+            "depth" => limits.depth = value.map(|d| d as usize),
+            "movetime" => limits.movetime = value,
+            "nodes" => limits.nodes = value,
+            "wtime" => wtime = value,
+            "btime" => btime = value,
+            "winc" => winc = value.unwrap_or(0),
+            "binc" => binc = value.unwrap_or(0),
+            "movestogo" => movestogo = value,
+            _ => {i += 1; continue;}
+        }
+        i += if args[i] == "infinite" {1} else {2};
+    }
+
+    let (time, inc) = if turn == PieceColor::White {(wtime, winc)} else {(btime, binc)};
+    if !infinite && limits.movetime.is_none() && let Some(time) = time {
+        limits.movetime = Some(time_budget(time, inc, movestogo));
+    }
+    (limits, infinite)
+}
+
+impl Uci {
+    fn new() -> Self {
+        let state = State::from_fen(START_FEN).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut searcher = Searcher::new(tt::DEFAULT_SIZE_MB);
+        searcher.stop = stop.clone();
+        Uci {
+            state,
+            history: Vec::new(),
+            searcher: Some(searcher),
+            hash_mb: tt::DEFAULT_SIZE_MB,
+            stop,
+            worker: None,
+        }
+    }
+
+    // Returns false once the engine should exit
+    fn handle(&mut self, line: &str) -> bool {
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+        let Some(&command) = tokens.first() else {return true};
+        match command {
+            "uci" => {
+                println!("id name chess-rust");
+                println!("id author chess-rust authors");
+                println!("option name Hash type spin default {} min 1 max {}", tt::DEFAULT_SIZE_MB, tt::MAX_SIZE_MB);
+                println!("option name Clear Hash type button");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => self.reset_hash(),
+            "setoption" => self.set_option(&tokens[1..]),
+            "position" => self.position(&tokens[1..]),
+            "go" => self.go(&tokens[1..]),
+            "stop" => self.stop_search(),
+            "d" => println!("Fen: {}", self.state.to_fen()),
+            "bench" => self.bench(tokens.get(1).and_then(|d| d.parse().ok()).unwrap_or(BENCH_DEPTH)),
+            "quit" => return false,
+            _ => println!("info string unknown command {}", command),
+        }
+        true
+    }
+
+    // Replaces the transposition table with an empty one of the configured size
+    fn reset_hash(&mut self) {
+        self.stop_search();
+        self.searcher.as_mut().unwrap().tt = TranspositionTable::new(self.hash_mb);
+    }
+
+    fn stop_search(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            self.searcher = Some(worker.join().unwrap());
+        }
+    }
+
+    // Searches every bench position from an empty table and reports node counts
+    fn bench(&mut self, depth: usize) {
+        self.stop_search();
+        let searcher = self.searcher.as_mut().unwrap();
+        let (mut total_nodes, mut total_time) = (0, 0);
+        for fen in BENCH_FENS {
+            let state = State::from_fen(fen).unwrap();
+            searcher.tt = TranspositionTable::new(self.hash_mb);
+            let res = searcher.search(&state, &[], Limits {depth: Some(depth), ..Limits::default()}, |_| {});
+            let time = res.iterations.last().map_or(0, |info| info.time);
+            println!("info string {} nodes {} time {}", fen, searcher.nodes, time);
+            total_nodes += searcher.nodes;
+            total_time += time;
+        }
+        searcher.tt = TranspositionTable::new(self.hash_mb);
+        println!("info string bench depth {} nodes {} time {} nps {}", depth, total_nodes, total_time, total_nodes * 1000 / total_time.max(1));
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        // setoption name <id with spaces> [value <x>]
+        let value_at = args.iter().position(|&t| t == "value");
+        let name = match args.get(1..value_at.unwrap_or(args.len())) {
+            Some(name) if args.first() == Some(&"name") => name.join(" "),
+            _ => {
+                println!("info string expected setoption name <id> [value <x>]");
+                return;
+            }
+        };
+        let value = value_at.map(|i| args[i + 1..].join(" "));
+
+        match (name.to_lowercase().as_str(), value) {
+            ("hash", Some(value)) => match value.parse::<usize>() {
+                Ok(mb) => {
+                    self.hash_mb = mb.clamp(1, tt::MAX_SIZE_MB);
+                    self.reset_hash();
+                }
+                Err(_) => println!("info string invalid Hash value {}", value),
+            },
+            ("clear hash", _) => self.reset_hash(),
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    // A position that can't be read is replaced by the initial one without its
+    // moves, and moves are played up to the first illegal one, so that `go`
+    // never searches a board the GUI didn't send
+    fn position(&mut self, args: &[&str]) {
+        let moves_at = args.iter().position(|&t| t == "moves").unwrap_or(args.len());
+        let parsed = match args.first() {
+            Some(&"startpos") => State::from_fen(START_FEN).map_err(|err| err.to_string()),
+            Some(&"fen") => State::from_fen(&args[1..moves_at].join(" ")).map_err(|err| format!("invalid fen: {}", err)),
+            _ => Err("expected startpos or fen".to_string()),
+        };
+        let mut state = match parsed {
+            Ok(state) => state,
+            Err(err) => {
+                println!("info string {}, using startpos", err);
+                self.state = State::from_fen(START_FEN).unwrap();
+                self.history = Vec::new();
+                return;
+            }
+        };
+
+        let mut history = Vec::new();
+        for &text in args.iter().skip(moves_at + 1) {
+            let Some(mv) = chess::parse_uci_move(&state, text) else {
+                println!("info string illegal move {}, ignoring the rest", text);
+                break;
+            };
+            history.push(state.zob_hash);
+            make_move_on(mv, &mut state);
+        }
+        let status = chess::game_status(&state, &history);
+        if status != chess::GameStatus::Ongoing {
+            println!("info string game over: {:?}", status);
+        }
+        self.state = state;
+        self.history = history;
+    }
+
+    fn go(&mut self, args: &[&str]) {
+        self.stop_search();
+
+        if args.first() == Some(&"perft") {
+            print_divide(&self.state, args.get(1).and_then(|d| d.parse().ok()).unwrap_or(1));
+            return;
+        }
+
+        let (limits, infinite) = go_limits(args, self.state.turn);
+        let mut searcher = self.searcher.take().unwrap();
+        let state = self.state.clone();
+        let history = self.history.clone();
+        let stop = self.stop.clone();
+        stop.store(false, Ordering::Relaxed);
+
+        self.worker = Some(thread::spawn(move || {
+            let res = searcher.search(&state, &history, limits, |info| println!("{}", format_info(info)));
+            // "go infinite" must not answer before being told to stop
+            while infinite && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            if res.best_move == Move::NULL {
+                println!("bestmove 0000");
+            } else {
+                println!("bestmove {}", res.best_move.to_uci());
+            }
+            searcher
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_setoption_is_ignored() {
+        let mut uci = Uci::new();
+        for line in ["setoption value 5", "setoption", "setoption name", "setoption value", "setoption Hash value 8"] {
+            assert!(uci.handle(line), "{}", line);
+        }
+        assert_eq!(uci.hash_mb, tt::DEFAULT_SIZE_MB);
+        assert!(uci.handle("setoption name Hash value 8"));
+        assert_eq!(uci.hash_mb, 8);
+    }
+
+    #[test]
+    fn positions() {
+        let mut uci = Uci::new();
+        uci.handle("position startpos moves e2e4 e7e5 g1f3");
+        assert_eq!(uci.state.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        assert_eq!(uci.history.len(), 3);
+        assert_eq!(uci.history[0], State::from_fen(START_FEN).unwrap().zob_hash);
+
+        uci.handle("position fen r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1 moves e1g1 e8c8");
+        assert_eq!(uci.state.to_fen(), "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2");
+        assert_eq!(uci.history.len(), 2);
+
+        // moves are played up to the first illegal one
+        uci.handle("position startpos moves e2e4 e2e4 e7e5");
+        assert_eq!(uci.state.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert_eq!(uci.history.len(), 1);
+
+        // an unreadable position never leaves the previous one in place
+        for line in ["position fen 8/8/8/8/8/8/8/8 w - - 0 1 moves e2e4", "position", "position e2e4"] {
+            uci.handle("position startpos moves d2d4");
+            uci.handle(line);
+            assert_eq!(uci.state.to_fen(), START_FEN, "{}", line);
+            assert!(uci.history.is_empty());
+        }
+    }
+
+    #[test]
+    fn go_limits_and_time_budget() {
+        assert_eq!(time_budget(60_000, 0, None), 2000);
+        assert_eq!(time_budget(60_000, 1000, Some(10)), 6750);
+        // never more than the clock minus a safety margin, and never nothing
+        assert_eq!(time_budget(1000, 5000, None), 950);
+        assert_eq!(time_budget(20, 0, None), 1);
+
+        let go = |line: &str, turn| go_limits(&line.split_whitespace().collect::<Vec<&str>>(), turn);
+        let (limits, infinite) = go("wtime 60000 btime 30000 winc 1000 binc 0", PieceColor::White);
+        assert_eq!((limits.movetime, limits.depth, infinite), (Some(2750), None, false));
+        let (limits, _) = go("wtime 60000 btime 30000 winc 1000 binc 0 movestogo 5", PieceColor::Black);
+        assert_eq!(limits.movetime, Some(6000));
+
+        let (limits, _) = go("depth 7 nodes 5000", PieceColor::White);
+        assert_eq!((limits.depth, limits.nodes, limits.movetime), (Some(7), Some(5000), None));
+        // an explicit movetime wins over the clock, infinite ignores it
+        let (limits, _) = go("movetime 100 wtime 60000", PieceColor::White);
+        assert_eq!(limits.movetime, Some(100));
+        let (limits, infinite) = go("infinite wtime 60000", PieceColor::White);
+        assert_eq!((limits.movetime, infinite), (None, true));
+        // unknown words and missing values are skipped
+        let (limits, _) = go("ponder depth x searchmoves e2e4 nodes 10", PieceColor::White);
+        assert_eq!((limits.depth, limits.nodes), (None, Some(10)));
+    }
+}
@@ -0,0 +1,436 @@
+use wasm_bindgen::prelude::*;
+use std::sync::{Mutex, OnceLock};
+
+use crate::chess::*;
+use crate::engine::Engine;
+use crate::game::Game;
+use crate::position::Position;
+use crate::search::{mate_in, Limits, SearchInfo};
+use crate::tt;
+use crate::{pgn, san, score};
+
+/// A game with its own position, move record and engine. Any number of them
+/// can live side by side, e.g. a live game next to an analysis board.
+#[wasm_bindgen]
+pub struct ChessGame {
+    game: Game,
+    engine: Option<Engine>, // created by the first engine_move
+    hash_mb: usize,
+    search_info: Vec<SearchInfo>, // of the last engine_move
+    pv: Vec<String>,
+}
+
+fn piece_type_from_name(name: &str) -> Option<PieceType> {
+    match name {
+        "Queen" => Some(PieceType::Queen),
+        "Rook" => Some(PieceType::Rook),
+        "Bishop" => Some(PieceType::Bishop),
+        "Knight" => Some(PieceType::Knight),
+        _ => None,
+    }
+}
+
+// "mate in 3", "mated in 2" or the score in centipawns such as "+35 cp"
+fn describe_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(n) if n > 0 => format!("mate in {}", n),
+        Some(n) => format!("mated in {}", -n),
+        None => format!("{:+} cp", score),
+    }
+}
+
+#[wasm_bindgen]
+impl ChessGame {
+    /// A new game from the initial position.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ChessGame {
+        ChessGame {
+            game: Game::new(State::from_fen(START_FEN).unwrap()),
+            engine: None,
+            hash_mb: tt::DEFAULT_SIZE_MB,
+            search_info: Vec::new(),
+            pv: Vec::new(),
+        }
+    }
+
+    /// An independent game with the same moves, e.g. to let the engine play on.
+    #[wasm_bindgen(js_name = clone)]
+    pub fn copy(&self) -> ChessGame {
+        ChessGame {
+            game: self.game.clone(),
+            engine: None,
+            hash_mb: self.hash_mb,
+            search_info: Vec::new(),
+            pv: Vec::new(),
+        }
+    }
+
+    /// Starts a new game from a FEN position.
+    pub fn set_fen(&mut self, fen: &str) -> Result<(), String> {
+        if fen.is_empty() {
+            return Err("FEN string cannot be empty".to_string());
+        }
+        let state = State::from_fen(fen).map_err(|err| format!("Invalid FEN: {}", err))?;
+        self.game = Game::new(state);
+        Ok(())
+    }
+
+    pub fn fen(&self) -> String {
+        self.game.state().to_fen()
+    }
+
+    /// The board, side to move, castling rights and counters as an object.
+    pub fn state(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.game.state()).unwrap()
+    }
+
+    /// Every legal move in coordinate notation, e.g. "e2e4".
+    pub fn legal_moves(&self) -> Vec<String> {
+        get_all_valid_moves(self.game.state()).iter().map(|mv| mv.to_uci()).collect()
+    }
+
+    /// Every legal move in algebraic notation, e.g. "Nf3".
+    pub fn legal_moves_san(&self) -> Vec<String> {
+        let state = self.game.state();
+        get_all_valid_moves(state).iter().map(|&mv| san::to_san(state, mv)).collect()
+    }
+
+    /// Destination squares (0 = a8 to 63 = h1) of the legal moves from `from`.
+    pub fn targets(&self, from: usize) -> Vec<usize> {
+        if from >= 64 {
+            return Vec::new();
+        }
+        let state = self.game.state();
+        if state.board[from].is_none_or(|p| p.color != state.turn) {
+            return Vec::new();
+        }
+        let mut targets = get_valid_moves_for(from, state).iter().map(|mv| mv.to()).collect::<Vec<usize>>();
+        targets.dedup(); // promotions give four moves to the same square
+        targets
+    }
+
+    /// Plays a move in coordinate ("e2e4", "e7e8q", "e1h1") or algebraic
+    /// ("Nf3", "O-O") notation and returns whether it was legal.
+    pub fn make_move(&mut self, text: &str) -> bool {
+        let state = self.game.state();
+        match parse_uci_move(state, text).or_else(|| san::parse_san(state, text)) {
+            Some(mv) => self.game.make_move(mv),
+            None => false,
+        }
+    }
+
+    /// Plays the move between two squares. `promotion` is "Queen", "Rook",
+    /// "Bishop" or "Knight" and defaults to a queen.
+    pub fn make_move_squares(&mut self, from: usize, to: usize, promotion: Option<String>) -> bool {
+        if from >= 64 {
+            return false;
+        }
+        let promotion = promotion.as_deref().and_then(piece_type_from_name).unwrap_or(PieceType::Queen);
+        let mv = get_valid_moves_for(from, self.game.state())
+            .into_iter()
+            .find(|mv| mv.to() == to && mv.promotion().is_none_or(|tp| tp == promotion));
+        match mv {
+            Some(mv) => self.game.make_move(mv),
+            None => false,
+        }
+    }
+
+    /// Thinks for `movetime` milliseconds when given, otherwise searches to a
+    /// fixed depth, and plays the best move. Returns the score for the side
+    /// that moved, e.g. "mate in 3" or "+35 cp".
+    pub fn engine_move(&mut self, movetime: Option<u32>) -> String {
+        let limits = match movetime {
+            Some(ms) => Limits {movetime: Some(ms as u64), ..Limits::default()},
+            None => Limits {depth: Some(4), ..Limits::default()},
+        };
+        let engine = self.engine.get_or_insert_with(|| Engine::with_hash_size(self.hash_mb));
+        let res = engine.search(&Position::from(&self.game), limits);
+        self.pv = san::to_san_line(self.game.state(), &res.pv);
+        self.game.make_move(res.best_move);
+        self.search_info = res.iterations;
+        describe_score(res.score)
+    }
+
+    /// Per-iteration depth/score/nodes/pv of the last engine search.
+    pub fn search_info(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.search_info).unwrap()
+    }
+
+    /// Principal variation of the last engine search in algebraic notation,
+    /// starting with the move the engine played.
+    pub fn pv(&self) -> Vec<String> {
+        self.pv.clone()
+    }
+
+    /// Takes back the last move; it can be replayed with redo until another move is made.
+    pub fn undo(&mut self) -> bool {
+        self.game.undo()
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.game.redo()
+    }
+
+    /// Moves through the game record so that the first `ply` moves are played.
+    pub fn goto_ply(&mut self, ply: usize) -> bool {
+        self.game.goto_ply(ply)
+    }
+
+    pub fn ply(&self) -> usize {
+        self.game.ply()
+    }
+
+    /// Every recorded move in the packed 16 bit encoding (from | to << 6 | flags << 12),
+    /// including undone ones after ply().
+    pub fn moves(&self) -> Vec<u16> {
+        self.game.moves().iter().map(|mv| mv.0).collect()
+    }
+
+    /// The recorded moves in algebraic notation, in the same order as moves().
+    pub fn moves_san(&self) -> Vec<String> {
+        san::to_san_line(&self.game.position(0).unwrap(), self.game.moves())
+    }
+
+    /// The position after the first `ply` recorded moves, like state(), or null.
+    pub fn position(&self, ply: usize) -> JsValue {
+        match self.game.position(ply) {
+            Some(state) => serde_wasm_bindgen::to_value(&state).unwrap(),
+            None => JsValue::NULL,
+        }
+    }
+
+    /// "Ongoing", "Checkmate", "Stalemate", "ThreefoldRepetition", "FiftyMoveRule",
+    /// "SeventyFiveMoveRule" or "InsufficientMaterial".
+    pub fn status(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.game.status()).unwrap()
+    }
+
+    pub fn in_check(&self) -> bool {
+        in_check_state(self.game.state())
+    }
+
+    /// Static evaluation in centipawns for the side to move.
+    pub fn eval(&self) -> i32 {
+        score::evaluate(self.game.state())
+    }
+
+    /// Replaces the game with the first game of a PGN text, positioned after its last move.
+    pub fn load_pgn(&mut self, pgn: &str) -> Result<(), String> {
+        let games = pgn::parse_pgn(pgn).map_err(|err| err.to_string())?;
+        let Some(record) = games.into_iter().next() else {
+            return Err("no game found".to_string());
+        };
+        let mut game = Game::new(record.start);
+        for mv in record.moves {
+            game.make_move(mv);
+        }
+        self.game = game;
+        Ok(())
+    }
+
+    /// The moves played so far as a PGN game, with the result filled in once the game is over.
+    pub fn pgn(&self) -> String {
+        let game = &self.game;
+        let result = pgn::result_for(game.status(), game.state().turn);
+        let record = pgn::PgnGame::new(game.position(0).unwrap(), game.moves()[..game.ply()].to_vec(), result);
+        record.to_pgn()
+    }
+
+    /// Replaces the engine's transposition table with an empty one of `size_mb` megabytes.
+    pub fn set_hash_size(&mut self, size_mb: usize) {
+        self.hash_mb = size_mb;
+        self.engine = None;
+    }
+
+    pub fn zob_hash(&self) -> String {
+        self.game.state().zob_hash.to_string()
+    }
+}
+
+impl Default for ChessGame {
+    fn default() -> Self {
+        ChessGame::new()
+    }
+}
+
+// The free functions below predate ChessGame and work on one shared default game
+
+static DEFAULT_GAME: OnceLock<Mutex<ChessGame>> = OnceLock::new();
+
+fn with_default_game<T>(f: impl FnOnce(&mut ChessGame) -> T) -> T {
+    let game = DEFAULT_GAME.get_or_init(|| Mutex::new(ChessGame::new()));
+    f(&mut game.lock().unwrap())
+}
+
+#[wasm_bindgen]
+pub fn set_state(fen: String) -> Result<(), String> {
+    with_default_game(|game| game.set_fen(&fen))
+}
+
+#[wasm_bindgen]
+pub fn get_state() -> JsValue {
+    with_default_game(|game| game.state())
+}
+
+#[wasm_bindgen]
+pub fn get_state_fen() -> String {
+    with_default_game(|game| game.fen())
+}
+
+#[wasm_bindgen]
+pub fn make_move(from: usize, to: usize, promotion: Option<String>) -> bool {
+    with_default_game(|game| game.make_move_squares(from, to, promotion))
+}
+
+#[wasm_bindgen]
+pub fn get_valid_moves(from: usize) -> Vec<usize> {
+    with_default_game(|game| game.targets(from))
+}
+
+#[wasm_bindgen]
+pub fn get_legal_moves() -> Vec<u16> {
+    with_default_game(|game| get_all_valid_moves(game.game.state()).iter().map(|mv| mv.0).collect())
+}
+
+#[wasm_bindgen]
+pub fn make_move_uci(uci: String) -> bool {
+    with_default_game(|game| match parse_uci_move(game.game.state(), &uci) {
+        Some(mv) => game.game.make_move(mv),
+        None => false,
+    })
+}
+
+#[wasm_bindgen]
+pub fn get_legal_moves_uci() -> Vec<String> {
+    with_default_game(|game| game.legal_moves())
+}
+
+#[wasm_bindgen]
+pub fn make_move_san(san: String) -> bool {
+    with_default_game(|game| match san::parse_san(game.game.state(), &san) {
+        Some(mv) => game.game.make_move(mv),
+        None => false,
+    })
+}
+
+#[wasm_bindgen]
+pub fn get_legal_moves_san() -> Vec<String> {
+    with_default_game(|game| game.legal_moves_san())
+}
+
+#[wasm_bindgen]
+pub fn get_moves_san() -> Vec<String> {
+    with_default_game(|game| game.moves_san())
+}
+
+#[wasm_bindgen]
+pub fn in_check() -> bool {
+    with_default_game(|game| game.in_check())
+}
+
+#[wasm_bindgen]
+pub fn get_game_status() -> JsValue {
+    with_default_game(|game| game.status())
+}
+
+#[wasm_bindgen]
+pub fn eval() -> i32 {
+    with_default_game(|game| game.eval())
+}
+
+#[wasm_bindgen]
+pub fn engine_move(movetime: Option<u32>) -> String {
+    with_default_game(|game| game.engine_move(movetime))
+}
+
+#[wasm_bindgen]
+pub fn get_search_info() -> JsValue {
+    with_default_game(|game| game.search_info())
+}
+
+#[wasm_bindgen]
+pub fn undo() -> bool {
+    with_default_game(|game| game.undo())
+}
+
+#[wasm_bindgen]
+pub fn redo() -> bool {
+    with_default_game(|game| game.redo())
+}
+
+#[wasm_bindgen]
+pub fn goto_ply(ply: usize) -> bool {
+    with_default_game(|game| game.goto_ply(ply))
+}
+
+#[wasm_bindgen]
+pub fn get_ply() -> usize {
+    with_default_game(|game| game.ply())
+}
+
+#[wasm_bindgen]
+pub fn get_moves() -> Vec<u16> {
+    with_default_game(|game| game.moves())
+}
+
+#[wasm_bindgen]
+pub fn get_position(ply: usize) -> JsValue {
+    with_default_game(|game| game.position(ply))
+}
+
+#[wasm_bindgen]
+pub fn load_pgn(pgn: String) -> Result<(), String> {
+    with_default_game(|game| game.load_pgn(&pgn))
+}
+
+#[wasm_bindgen]
+pub fn get_pgn() -> String {
+    with_default_game(|game| game.pgn())
+}
+
+#[wasm_bindgen]
+pub fn get_pv() -> Vec<String> {
+    with_default_game(|game| game.pv())
+}
+
+#[wasm_bindgen]
+pub fn set_hash_size(size_mb: usize) {
+    with_default_game(|game| game.set_hash_size(size_mb))
+}
+
+#[wasm_bindgen]
+pub fn get_zob_hash() -> String {
+    with_default_game(|game| game.zob_hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_are_independent() {
+        let mut a = ChessGame::new();
+        let mut b = ChessGame::new();
+        assert!(a.make_move("e4"));
+        assert!(b.make_move("d2d4"));
+        let mut c = a.copy();
+        assert!(c.make_move("e5"));
+        assert_eq!(a.moves_san(), ["e4"]);
+        assert_eq!(b.moves_san(), ["d4"]);
+        assert_eq!(c.moves_san(), ["e4", "e5"]);
+        assert!(c.engine.is_none());
+
+        assert!(a.undo());
+        assert_eq!(a.ply(), 0);
+        assert_eq!(c.ply(), 2);
+        assert_eq!(b.fen(), "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 1");
+
+        b.set_fen("7k/8/6K1/8/8/8/8/5Q2 w - - 0 1").unwrap();
+        b.set_hash_size(1);
+        assert_eq!(b.engine_move(None), "mate in 1");
+        assert_eq!(b.pv(), ["Qf8#"]);
+        assert!(a.pv().is_empty() && a.engine.is_none());
+        assert_eq!(a.fen(), START_FEN);
+        assert_eq!(c.fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+    }
+}
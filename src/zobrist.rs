@@ -26,7 +26,7 @@ pub struct Keys {
 //     Keys { pieces: piece_rand, turn: turn, castling: castling, enpassant: enpassant }
 // }
 
-pub const ZOBRIST_KEYS: Keys = Keys {
+pub static ZOBRIST_KEYS: Keys = Keys {
     pieces: [
         [
             1547756390519538975,